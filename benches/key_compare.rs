@@ -0,0 +1,80 @@
+//! Station name comparisons done on every hash table lookup: the whole 100-byte buffer,
+//! as the tables used to compare them, against the length-aware comparisons of
+//! `onebrc::arena`, for short and long names.
+//!
+//!     cargo bench --bench key_compare
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use onebrc::arena::{names_equal, Entry, NameArena};
+
+const NAME_LENGTHS: [usize; 6] = [4, 8, 13, 32, 64, 100];
+
+// a zero padded name of `len` bytes, like the one the scanners fill
+fn station(len: usize, last: u8) -> [u8; 100] {
+    let mut station = [0; 100];
+    for (idx, byte) in station[..len].iter_mut().enumerate() {
+        *byte = b'a' + (idx % 26) as u8;
+    }
+    if len > 0 {
+        station[len - 1] = last;
+    }
+    station
+}
+
+fn compare(c: &mut Criterion) {
+    // a hit is a lookup of the station in the slot, a miss a collision with a station
+    // of the same length that only differs in its last byte
+    for (group_name, last, other_hash) in [("compare_hit", b'x', 42), ("compare_miss", b'y', 43)] {
+        let mut group = c.benchmark_group(group_name);
+        for len in NAME_LENGTHS {
+            let stored = station(len, b'x');
+            let looked_up = station(len, last);
+            let mut names = NameArena::new();
+            let entry = Entry::new(42, names.push(&stored[..len]), len as u32, 0);
+
+            group.bench_with_input(BenchmarkId::new("array_100", len), &len, |b, _| {
+                b.iter(|| black_box(&stored) == black_box(&looked_up))
+            });
+            group.bench_with_input(BenchmarkId::new("slice", len), &len, |b, &len| {
+                b.iter(|| black_box(&stored[..len]) == black_box(&looked_up[..len]))
+            });
+            group.bench_with_input(BenchmarkId::new("names_equal", len), &len, |b, &len| {
+                b.iter(|| names_equal(black_box(&stored[..len]), black_box(&looked_up[..len])))
+            });
+            group.bench_with_input(BenchmarkId::new("entry_matches", len), &len, |b, &len| {
+                b.iter(|| entry.matches(&names, black_box(other_hash), black_box(&looked_up[..len])))
+            });
+        }
+        group.finish();
+    }
+}
+
+// 400 stations whose names share all but their last 4 bytes, the worst case for the
+// comparisons
+fn measurements(name_len: usize, rows: usize) -> Vec<u8> {
+    let mut contents = String::new();
+    for row in 0..rows {
+        let station = row * 7919 % 400;
+        let temp = (row * 31 % 1999) as i32 - 999;
+        let sign = if temp < 0 { "-" } else { "" };
+        contents.push_str(&format!("{}{:04};{}{}.{}\n", "x".repeat(name_len - 4), station, sign, temp.abs() / 10, temp.abs() % 10));
+    }
+    contents.into_bytes()
+}
+
+fn aggregate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aggregate");
+    for name_len in [6, 24, 96] {
+        let contents = measurements(name_len, 200_000);
+        group.throughput(Throughput::Bytes(contents.len() as u64));
+        group.bench_with_input(BenchmarkId::new("attempt10", name_len), &contents, |b, contents| {
+            b.iter(|| onebrc::aggregate_bytes(contents, 1).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compare, aggregate);
+criterion_main!(benches);
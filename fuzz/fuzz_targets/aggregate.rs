@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use onebrc::differential;
+use onebrc::hash::DefaultHash;
+use onebrc::schedule;
+use onebrc::validate::{self, ErrorPolicy};
+
+// The first two bytes pick the size of the ranges and the number of workers, the rest
+// is the input. Whatever the bytes, attempt10 must neither read out of bounds nor
+// panic, and must agree with the line by line parser.
+fuzz_target!(|data: &[u8]| {
+    let Some((&[chunk_size, thread_count], contents)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let chunk_size = chunk_size as usize + 1;
+    let thread_count = thread_count as usize % 4 + 1;
+    let precision = validate::detect_precision(contents);
+
+    let workers = schedule::distribute_work::<DefaultHash>(&[contents], thread_count, ErrorPolicy::Skip, Some(precision), chunk_size).unwrap_or_else(|e| panic!("{}", e));
+    let actual = schedule::merge_workers(workers, 1).remove(0).expect("no row stops the workers when skipping");
+    assert_eq!(actual, differential::oracle(contents, precision), "ranges of {} bytes with {} threads", chunk_size, thread_count);
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use onebrc::scan::{Delimiters, Kernel, BLOCK_SIZE};
+
+// Every kernel finds the same delimiters as a byte by byte search, from any offset.
+fuzz_target!(|contents: &[u8]| {
+    let scalar = |from: usize, newline_only: bool| contents.iter().skip(from)
+        .position(|&b| b == b'\n' || (b == b';' && !newline_only))
+        .map_or(contents.len(), |idx| from + idx);
+    for kernel in Kernel::ALL.into_iter().filter(|kernel| kernel.is_supported()) {
+        let mut delimiters = Delimiters::with_kernel(contents, kernel);
+        for from in 0..=contents.len() {
+            assert_eq!(delimiters.next_delimiter(from), scalar(from, false), "{} from {}", kernel.name(), from);
+            assert_eq!(delimiters.next_newline(from), scalar(from, true), "{} from {}", kernel.name(), from);
+        }
+        if let Some(block) = contents.first_chunk::<BLOCK_SIZE>() {
+            assert_eq!(kernel.masks(block), Kernel::Swar.masks(block), "{}", kernel.name());
+        }
+    }
+});
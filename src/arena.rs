@@ -0,0 +1,154 @@
+use crate::utils::KeyedStat;
+
+/// Station names of a per-thread hash table, stored back to back so that the table
+/// slots only hold an offset and a length.
+#[derive(Debug, Default)]
+pub struct NameArena {
+    bytes: Vec<u8>,
+}
+
+impl NameArena {
+    pub fn new() -> NameArena {
+        NameArena::default()
+    }
+
+    /// Appends `name` and returns its offset.
+    pub fn push(&mut self, name: &[u8]) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name);
+        offset
+    }
+
+    pub fn get(&self, offset: u32, len: u32) -> &[u8] {
+        &self.bytes[offset as usize..(offset + len) as usize]
+    }
+
+    /// Total size of the names, in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+/// Compares two names only up to their length. Names of up to 16 bytes, most of them,
+/// are compared as two overlapping words, which is faster than a `memcmp` call. The
+/// longer ones go through `memcmp`, which is vectorized.
+#[inline]
+pub fn names_equal(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len();
+    if len != b.len() {
+        return false;
+    }
+    let word = |bytes: &[u8], idx: usize| u64::from_ne_bytes(bytes[idx..idx + 8].try_into().unwrap());
+    let half_word = |bytes: &[u8], idx: usize| u32::from_ne_bytes(bytes[idx..idx + 4].try_into().unwrap());
+    match len {
+        0..=3 => a == b,
+        4..=7 => half_word(a, 0) == half_word(b, 0) && half_word(a, len - 4) == half_word(b, len - 4),
+        8..=16 => word(a, 0) == word(b, 0) && word(a, len - 8) == word(b, len - 8),
+        _ => a == b,
+    }
+}
+
+/// Hash table record for a station whose name is in a `NameArena`, 40 bytes instead
+/// of the 136 of a `KeyedStat`. A `len` of 0 marks an empty slot.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Entry {
+    pub hash: usize,
+    pub sum: i64,
+    pub count: u64,
+    pub min: i32,
+    pub max: i32,
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl Entry {
+    /// A record for the first measurement of a station.
+    pub fn new(hash: usize, offset: u32, len: u32, temp: i32) -> Entry {
+        Entry { hash, sum: temp as i64, count: 1, min: temp, max: temp, offset, len }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn update(&mut self, temp: i32) {
+        self.sum += temp as i64;
+        self.count += 1;
+        self.min = temp.min(self.min);
+        self.max = temp.max(self.max);
+    }
+
+    pub fn name<'a>(&self, names: &'a NameArena) -> &'a [u8] {
+        names.get(self.offset, self.len)
+    }
+
+    /// Whether the record is for the station `name` with the given hash. The hash and
+    /// the length rule out almost all the other stations before any byte is compared.
+    #[inline]
+    pub fn matches(&self, names: &NameArena, hash: usize, name: &[u8]) -> bool {
+        self.hash == hash && self.len as usize == name.len() && names_equal(self.name(names), name)
+    }
+
+    /// The stats in the layout of the results.
+    pub fn to_keyed_stat(&self, names: &NameArena, precision: u32) -> KeyedStat {
+        let mut ks = KeyedStat {
+            min: self.min,
+            max: self.max,
+            sum: self.sum,
+            count: self.count,
+            len: self.len as usize,
+            precision,
+            ..KeyedStat::new()
+        };
+        ks.station[..ks.len].copy_from_slice(self.name(names));
+        ks
+    }
+}
+
+
+#[test]
+fn test_arena() {
+    assert_eq!(std::mem::size_of::<Entry>(), 40);
+    assert_eq!(std::mem::size_of::<KeyedStat>(), 136);
+
+    let mut names = NameArena::new();
+    let hamburg = Entry::new(1, names.push(b"Hamburg"), 7, 120);
+    let mut bulawayo = Entry::new(2, names.push("Bulawayo".as_bytes()), 8, 89);
+    bulawayo.update(-35);
+    assert_eq!(names.size(), 15);
+    assert_eq!(hamburg.name(&names), b"Hamburg");
+    assert_eq!(bulawayo.name(&names), b"Bulawayo");
+
+    assert!(bulawayo.matches(&names, 2, b"Bulawayo"));
+    assert!(!bulawayo.matches(&names, 3, b"Bulawayo"));
+    assert!(!bulawayo.matches(&names, 2, b"Bulawayo "));
+    assert!(!hamburg.matches(&names, 1, b"Hamburh"));
+
+    let ks = bulawayo.to_keyed_stat(&names, 1);
+    assert_eq!((ks.min, ks.max, ks.sum, ks.count, ks.len), (-35, 89, 54, 2, 8));
+    assert_eq!(&ks.station[..9], b"Bulawayo\0");
+    assert!(Entry::default().is_empty());
+}
+
+#[test]
+fn test_names_equal() {
+    let name: Vec<u8> = (0..100).collect();
+    for len in 0..=100 {
+        let mut other = name[..len].to_vec();
+        assert!(names_equal(&name[..len], &other));
+        for idx in 0..len {
+            other[idx] ^= 1;
+            assert!(!names_equal(&name[..len], &other), "length {}, byte {}", len, idx);
+            other[idx] ^= 1;
+        }
+        if len > 0 {
+            assert!(!names_equal(&name[..len], &name[..len - 1]));
+        }
+    }
+    // a shorter name is not a prefix match, even with zero bytes
+    assert!(!names_equal(b"ab\0", b"ab"));
+}
@@ -1,97 +1,43 @@
-use std::{collections::HashMap, fs::{self}, ptr, time::Instant};
+use std::{fs::{self}, io, ptr, time::Instant};
 use std::collections::BTreeMap;
-use log::{debug, info};
+use log::debug;
 
-use crate::utils::{KeyedStat, Stat};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::{parse_tenths, KeyedStat};
+use crate::validate::Rejected;
 
-fn read_file(path: &str) -> String {
+fn read_file(path: &str) -> io::Result<String> {
     let start = Instant::now();
-    let contents = fs::read_to_string(path).unwrap();
+    let contents = fs::read_to_string(path)?;
     let end = Instant::now();
     debug!("Time taken to read the file: {} milliseconds", (end-start).as_millis());
-    contents
+    Ok(contents)
 }
 
-fn compute_to_hashmap(contents: String) -> HashMap<String, Stat> {
-    let start = Instant::now();
-    let mut table: HashMap<String, Stat> = HashMap::new();
-
-    for line in contents.lines() {
-        if let Some((station, stemp)) = line.split_once(';') {
-            let temp: f32 = stemp.parse().unwrap();
-            let maybe_stat = table.get_mut(station);
-            match maybe_stat {
-                None => {
-                    table.insert(String::from(station), Stat {
-                        min: temp,
-                        max: temp,
-                        count: 1.0,
-                        sum: temp,
-                    });
-                },
-                Some(stat) => {
-                    stat.sum += temp;
-                    stat.count += 1.0;
-                    stat.min = temp.min(stat.min);
-                    stat.max = temp.max(stat.max);
-                }
-            }
-        }
-    }
-    let end = Instant::now();
-    debug!("Time taken to compute the stats: {} milliseconds", (end-start).as_millis());
-    table
-}
-
-fn compute_to_btree_stat(contents: String) -> BTreeMap<String, Stat> {
-    let start = Instant::now();
-    let mut table: BTreeMap<String, Stat> = BTreeMap::new();
-
-    for line in contents.lines() {
-        if let Some((station, stemp)) = line.split_once(';') {
-            let temp: f32 = stemp.parse().unwrap();
-            let maybe_stat = table.get_mut(station);
-            match maybe_stat {
-                None => {
-                    table.insert(String::from(station), Stat {
-                        min: temp,
-                        max: temp,
-                        count: 1.0,
-                        sum: temp,
-                    });
-                },
-                Some(stat) => {
-                    stat.sum += temp;
-                    stat.count += 1.0;
-                    stat.min = temp.min(stat.min);
-                    stat.max = temp.max(stat.max);
-                }
-            }
-        }
-    }
-    let end = Instant::now();
-    debug!("Time taken to compute the stats: {} milliseconds", (end-start).as_millis());
-    table
+// the naive functions are the reference of the tests, they panic if the file cannot be read
+fn read_file_or_panic(path: &str) -> String {
+    read_file(path).unwrap_or_else(|e| panic!("{}: {}", path, e))
 }
 
-
 fn compute_to_btree_kstat(contents: String) -> BTreeMap<String, KeyedStat> {
     let start = Instant::now();
     let mut table: BTreeMap<String, KeyedStat> = BTreeMap::new();
 
     for line in contents.lines() {
         if let Some((station, stemp)) = line.split_once(';') {
-            let temp: f32 = stemp.parse().unwrap();
+            let temp = parse_tenths(stemp);
             let maybe_stat = table.get_mut(station);
             match maybe_stat {
                 None => {
                     let mut ks = KeyedStat {
                         min: temp,
                         max: temp,
-                        count: 1.0,
-                        sum: temp,
+                        count: 1,
+                        sum: temp as i64,
                         station: [0; 100],
                         len: station.len(),
+                        precision: 1,
                     };
                     unsafe {
                         ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), station.len());
@@ -99,8 +45,8 @@ fn compute_to_btree_kstat(contents: String) -> BTreeMap<String, KeyedStat> {
                     table.insert(String::from(station), ks);
                 },
                 Some(stat) => {
-                    stat.sum += temp;
-                    stat.count += 1.0;
+                    stat.sum += temp as i64;
+                    stat.count += 1;
                     stat.min = temp.min(stat.min);
                     stat.max = temp.max(stat.max);
                 }
@@ -114,23 +60,27 @@ fn compute_to_btree_kstat(contents: String) -> BTreeMap<String, KeyedStat> {
 
 
 
-pub fn naive_hashmap(path: &str) -> HashMap<String, Stat> {
-    let contents = read_file(path);
-    let table = compute_to_hashmap(contents);
-    crate::utils::print_result_hashmap(&table);
-    table
+pub fn naive_btree_kstat(path: &str) -> BTreeMap<String, KeyedStat> {
+    let contents = read_file_or_panic(path);
+    compute_to_btree_kstat(contents)
 }
 
-pub fn naive_btree_stat(path: &str) -> BTreeMap<String, Stat> {
-    let contents = read_file(path);
-    let table = compute_to_btree_stat(contents);
-    crate::utils::print_result_btreemap_stat(&table);
-    table
-}
+pub struct Attempt1;
 
-pub fn naive_btree_kstat(path: &str) -> BTreeMap<String, KeyedStat> {
-    let contents = read_file(path);
-    let table = compute_to_btree_kstat(contents);
-    crate::utils::print_result_btreemap_kstat(&table);
-    table
+impl Solver for Attempt1 {
+    fn name(&self) -> &'static str {
+        "attempt1"
+    }
+
+    fn description(&self) -> &'static str {
+        "Single thread, reads the whole file into a String and aggregates into a BTreeMap"
+    }
+
+    fn solve(&self, input: &str, _config: &SolverConfig) -> Results {
+        naive_btree_kstat(input)
+    }
+
+    fn try_solve(&self, input: &str, _config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((compute_to_btree_kstat(read_file(input)?), Rejected::default()))
+    }
 }
@@ -1,421 +1,690 @@
-use core::num;
-use std::{arch::asm, collections::BTreeMap, env, fs::{self, File}, io::{Read, Seek, SeekFrom}, mem, ptr, thread::{self, current, JoinHandle}, time::Instant};
-
-use crate::{attempt1, utils::{print_result_btreemap_kstat, print_result_hashmap, KeyedStat, Stat, MAX_LINE_SIZE, THREAD_COUNT}};
-use log::{debug, info};
+use std::{collections::BTreeMap, fs::File, mem};
+
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::arena::{Entry, NameArena};
+use crate::error::Error;
+use crate::hash::NameHash;
+use crate::scan::Delimiters;
+use crate::schedule;
+use crate::utils::KeyedStat;
+use crate::validate::{self, ErrorPolicy, Rejected, RowError, RowErrorKind, MAX_NAME_LEN, MAX_PRECISION};
+use log::debug;
 use std::str;
-use memmap2::{Mmap, MmapOptions};
-
-
-struct LPTable {
-    table: Vec<KeyedStat>,
-    num_slots: usize,
+use memmap2::Mmap;
+
+
+/// Most distinct stations a table accepts, the challenge has at most 10k of them.
+pub const MAX_STATIONS: usize = 10_000;
+/// Initial number of slots of the per-thread tables, enough for the 413 stations of
+/// the standard dataset.
+pub const INITIAL_SLOTS: usize = 4096;
+
+/// Open addressing hash table with linear probing. The number of slots is a power of
+/// two and doubles whenever the table gets more than half full, so that probing always
+/// finds an empty slot. The slots hold compact records, the names are in an arena.
+pub struct LPTable {
+    table: Vec<Entry>,
+    names: NameArena,
+    mask: usize,
+    // 64 - log2(number of slots)
+    shift: u32,
+    len: usize,
     collision_count: usize,
     insert_count: usize,
 }
 
 
 impl LPTable {
-    fn new(num_slots: usize) -> LPTable {
-        let mut lptable = LPTable {
-            table: Vec::new(),
-            num_slots: num_slots,
+    /// A table with at least `num_slots` slots, rounded up to a power of two.
+    pub fn new(num_slots: usize) -> LPTable {
+        let num_slots = num_slots.max(2).next_power_of_two();
+        LPTable {
+            table: vec![Entry::default(); num_slots],
+            names: NameArena::new(),
+            mask: num_slots - 1,
+            shift: usize::BITS - num_slots.trailing_zeros(),
+            len: 0,
             collision_count: 0,
             insert_count: 0,
-        };
+        }
+    }
 
-        lptable.table.resize(num_slots, KeyedStat::new());
-        lptable
+    // Fibonacci hashing: the top bits of the product depend on all the bits of the
+    // hash, whose low bits barely change between names with the same first bytes.
+    fn home_slot(&self, hash: usize) -> usize {
+        hash.wrapping_mul(0x9E3779B97F4A7C15) >> self.shift
     }
 
-    fn is_slot_empty(&mut self, slot: usize) -> bool {
-        self.table[slot].len == 0
+    fn is_slot_empty(&self, slot: usize) -> bool {
+        self.table[slot].is_empty()
     }
 
+    /// Number of distinct stations in the table.
+    pub fn len(&self) -> usize {
+        self.len
+    }
 
-    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: f32) {
-        let mut slot = hash & (self.num_slots-1);
-        let initial_slot = slot;
-        debug!("station={}, hash={}, temp={}", str::from_utf8(&station[0..len]).unwrap(), hash, temp);
+    pub fn capacity(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Fraction of the slots in use.
+    pub fn load_factor(&self) -> f64 {
+        self.len as f64 / self.capacity() as f64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert_count(&self) -> usize {
+        self.insert_count
+    }
+
+    pub fn collision_count(&self) -> usize {
+        self.collision_count
+    }
+
+    /// Empties all the slots, keeping the allocations.
+    pub fn clear(&mut self) {
+        self.table.fill(Entry::default());
+        self.names.clear();
+        self.len = 0;
+    }
+
+    /// Iterates over the stations in the occupied slots.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.table.iter().filter(|entry| !entry.is_empty()).map(|entry| {
+            // names are checked when they are inserted
+            (unsafe { str::from_utf8_unchecked(entry.name(&self.names)) }, entry)
+        })
+    }
+
+
+    // Returns false if the station is new and its name is not valid UTF-8. Names are
+    // only checked once, when they are first inserted. Fails if the station would be
+    // one more than MAX_STATIONS.
+    fn insert_or_update(&mut self, name: &[u8], hash: usize, temp: i32) -> Result<bool, RowErrorKind> {
+        let mut slot = self.home_slot(hash);
+        debug!("station={}, hash={}, temp={}", String::from_utf8_lossy(name), hash, temp);
 
         self.insert_count += 1;
 
-        while true {
+        loop {
             if self.is_slot_empty(slot) {
-                let mut ks = KeyedStat{
-                    min: temp,
-                    max: temp,
-                    count: 1.0,
-                    len: len,
-                    sum: temp,
-                    station: [0;100],
-                };
-                unsafe {
-                    ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), ks.station.len());
+                if str::from_utf8(name).is_err() {
+                    return Ok(false);
                 }
-                self.table[slot] = ks;
-                break;
+                if self.len == MAX_STATIONS {
+                    return Err(RowErrorKind::TooManyStations(MAX_STATIONS));
+                }
+                if 2 * (self.len + 1) > self.capacity() {
+                    self.grow();
+                    slot = self.home_slot(hash);
+                    while !self.is_slot_empty(slot) {
+                        slot = (slot + 1) & self.mask;
+                    }
+                }
+                let offset = self.names.push(name);
+                self.table[slot] = Entry::new(hash, offset, name.len() as u32, temp);
+                self.len += 1;
+                return Ok(true);
             } else {
-                if self.table[slot].station != *station {
-                    slot = (slot + 1) % (self.num_slots-1);
+                if !self.table[slot].matches(&self.names, hash, name) {
+                    slot = (slot + 1) & self.mask;
                     self.collision_count += 1;
                     continue;
                 } 
-                self.table[slot].sum += temp;
-                self.table[slot].count += 1.0;
-                self.table[slot].min  = temp.min(self.table[slot].min);
-                self.table[slot].max = temp.max(self.table[slot].max);
-                break;
+                self.table[slot].update(temp);
+                return Ok(true);
             }
         }
     }
 
-}
-
+    // Doubles the number of slots and reinserts the stations.
+    fn grow(&mut self) {
+        let num_slots = 2 * self.capacity();
+        debug!("growing the table to {} slots", num_slots);
+        let table = mem::replace(&mut self.table, vec![Entry::default(); num_slots]);
+        self.mask = num_slots - 1;
+        self.shift -= 1;
+        for entry in table.into_iter().filter(|entry| !entry.is_empty()) {
+            let mut slot = self.home_slot(entry.hash);
+            while !self.is_slot_empty(slot) {
+                slot = (slot + 1) & self.mask;
+            }
+            self.table[slot] = entry;
+        }
+    }
 
-struct Cursor {
-    temp_int_part: u8,
-    temp_fraction_part: u8,
-    parsing_name: bool,
-    parsing_int_part: bool,
-    station_idx: usize,
-    hash: usize,
-    temp_multiplier: f32,
-    station: [u8; 100],
 }
 
-impl Cursor {
-    fn new() -> Cursor {
-        return Cursor {
-            hash: 5381,
-            station: [0; 100],
-            station_idx: 0,
-            parsing_name: true,
-            temp_int_part: 0,
-            temp_fraction_part: 0,
-            temp_multiplier: 1.0,
-            parsing_int_part: true
-        };
+
+// The temperature field at `contents[start..end]` as a little-endian word, zero
+// padded past the end of the input. The bytes past the field are left as they are.
+#[inline]
+fn temperature_word(contents: &[u8], start: usize) -> u64 {
+    match contents[start..].first_chunk::<8>() {
+        Some(bytes) => u64::from_le_bytes(*bytes),
+        None => {
+            let mut bytes = [0; 8];
+            bytes[..contents.len() - start].copy_from_slice(&contents[start..]);
+            u64::from_le_bytes(bytes)
+        }
     }
 }
 
-fn temprature(c: &mut Cursor) -> f32 {
-    c.temp_multiplier * (f32::try_from(c.temp_int_part).unwrap() + f32::from(c.temp_fraction_part) / 10.0)
-}
-    
-fn reset(c: &mut Cursor) {
-    c.station = [0; 100];
-    c.station_idx = 0;
-    c.hash = 5381;
-    c.parsing_name = true;
-    c.temp_int_part = 0;
-    c.temp_fraction_part = 0;
-    c.temp_multiplier = 1.0;
-    c.parsing_int_part = true;
+// Temperature in tenths of a degree from the first `len` bytes of `word`, None unless
+// they are of the form -?d?d.d. No branch until the final check: of the bytes of a
+// valid field only '-' (0x2D) and '.' (0x2E) have bit 4 clear, the digits are 0x3X,
+// which locates the dot and tells the sign. The digits are then shifted to fixed
+// positions and combined with a single multiplication.
+#[inline]
+fn temperature_swar(word: u64, len: usize) -> Option<i32> {
+    const LOW_BITS: u64 = 0x7F7F7F7F7F7F7F7F;
+    // bit 12, 20 or 28 for a dot in byte 1, 2 or 3, 64 if there is none
+    let dot = (!word & 0x10101000).trailing_zeros();
+    let dot_idx = (dot / 8) as usize;
+    // all ones when the first byte is a '-'
+    let negative = ((!word << 59) as i64 >> 63) as u64;
+    let unsigned = word & !(negative & 0xFF);
+    // the digits as 0x0F000F0F00 (tens, units, tenths), the tens being 0 if there are none
+    let digits = unsigned.wrapping_shl(28u32.wrapping_sub(dot)) & 0x0F000F0F00;
+    let abs = ((digits.wrapping_mul(0x640a0001) >> 32) & 0x3FF) as i64;
+    let value = (abs ^ negative as i64) - negative as i64;
+
+    // every byte of the field but the sign and the dot is a digit
+    let field = u64::MAX >> (64 - 8 * len.clamp(1, 8));
+    let digit_bytes = field & !(0xFF << (8 * dot_idx.min(7))) & !(negative & 0xFF);
+    let x = word ^ 0x3030303030303030;
+    let non_digits = (((x & LOW_BITS) + 0x7676767676767676) | x) & 0x8080808080808080;
+    let sign_len = (negative & 1) as usize;
+    let valid = (len == dot_idx + 2)
+        & (non_digits & digit_bytes == 0)
+        & ((word >> (8 * dot_idx.min(7))) as u8 == b'.')
+        & (negative == 0 || word as u8 == b'-')
+        & (dot_idx > sign_len)
+        & (dot_idx <= sign_len + 2);
+    valid.then_some(value as i32)
 }
 
-fn update_temprature(c: &mut Cursor, byte: u8) {
-    if byte == b'.' {
-        c.parsing_int_part = false;
-        return;
-    } else if byte == b'-' {
-        c.temp_multiplier = -1.0;
-        return;
+// Hashes the name at `contents[start..start + len]` like `hash::hash_name`, reading
+// the last word straight from the input when it is not at its very end.
+#[inline]
+fn hash_name_at<H: NameHash>(contents: &[u8], start: usize, len: usize) -> usize {
+    let word_at = |offset: usize| usize::from_le_bytes(contents[offset..offset + 8].try_into().unwrap());
+    let mut hash = H::SEED;
+    let mut offset = start;
+    let end = start + len;
+    while offset + 8 <= end {
+        hash = H::write(hash, word_at(offset));
+        offset += 8;
     }
-
-    let digit = byte - b'0';
-    if c.parsing_int_part {
-        c.temp_int_part = (c.temp_int_part * 10) + digit;
-    } else {
-        c.temp_fraction_part = (c.temp_fraction_part * 10) + digit;
+    if offset < end {
+        let word = if offset + 8 <= contents.len() {
+            word_at(offset) & (usize::MAX >> (64 - 8 * (end - offset)))
+        } else {
+            let mut word = [0; 8];
+            word[..end - offset].copy_from_slice(&contents[offset..end]);
+            usize::from_le_bytes(word)
+        };
+        hash = H::write(hash, word);
     }
+    hash
 }
 
-unsafe fn read_unaligned_u64(ptr: *const usize) -> usize {
-    let mut result: usize = 1;
 
-    asm!(
-        "mov {result}, qword ptr [{ptr}]",
-        ptr = in(reg) ptr,
-        result = out(reg) result,
-    );
+/// What a worker produced for its byte range.
+pub struct Chunk {
+    pub table: LPTable,
+    /// Lines that start in the range, including the rejected ones.
+    pub lines: usize,
+    pub rejected: Rejected,
+    /// Fractional digits the temperatures were parsed with.
+    pub precision: u32,
+    /// First malformed row under `ErrorPolicy::Strict`, the worker stops there. Its
+    /// line number is relative to the start of the range until the chunks are merged.
+    pub error: Option<RowError>,
+    /// Line, offset and name of the rows that added a station to the table, in order,
+    /// so that `MAX_STATIONS` can be enforced on the merged tables.
+    pub added: Vec<(usize, usize, Box<[u8]>)>,
+}
 
-    result
+impl Chunk {
+    pub fn new(precision: u32) -> Chunk {
+        Chunk {
+            table: LPTable::new(INITIAL_SLOTS),
+            lines: 0,
+            rejected: Rejected::default(),
+            precision,
+            error: None,
+            added: Vec::new(),
+        }
+    }
 }
 
-fn find_newline(data: usize) -> i32 {
-    let x = data ^ 0x0A0A0A0A0A0A0A0A;
-    let y = (x-0x0101010101010101) & !x;
+/// Aggregates the lines that start within `contents[..=bytes_to_process]` into
+/// `chunk`, `start_offset` being the offset of `contents` in the whole input. Station
+/// names are hashed with `H`.
+pub(crate) fn compute_into<H: NameHash>(chunk: &mut Chunk, contents: &[u8], ignore_first_line: bool, bytes_to_process: isize, start_offset: usize, policy: ErrorPolicy) {
+    let precision = chunk.precision;
+    let mut delimiters = Delimiters::new(contents);
+    let mut buf_idx = 0;
 
-    let z = y & (0x8080808080808080);
-    if z == 0 {
-        return -1;
+    if ignore_first_line {
+        buf_idx = delimiters.next_newline(0) + 1;
     }
-    return (y.trailing_zeros() >> 3) as i32;
-}
 
-fn find_next_newline_vectorized(ptr: *const u8,  max_offset: isize) -> isize {
-    let mut offset = 0;
-    let mut bytes_remaining: isize = max_offset-offset+1;
-    while bytes_remaining >= 8 {
-        unsafe {
-            let data: usize = read_unaligned_u64(ptr.offset(offset) as *const usize);
-            let newline_idx = find_newline(data);
-            if newline_idx == -1 {
-                offset += 8;
-                bytes_remaining -= 8;
+    loop {
+        if buf_idx as isize > bytes_to_process || buf_idx >= contents.len() {
+            break;
+        }
+        let line_start = buf_idx;
+        chunk.lines += 1;
+
+        let name_end = delimiters.next_delimiter(line_start);
+        let name = &contents[line_start..name_end];
+        let valid_name = (1..=MAX_NAME_LEN).contains(&name.len())
+            && contents.get(name_end) == Some(&b';')
+            && name[0] != b'#';
+
+        let mut valid = false;
+        if valid_name {
+            let temp_start = name_end + 1;
+            let temp_end = delimiters.next_newline(temp_start);
+            // the standard dataset always has one decimal, other precisions use the generic parser
+            let temp = if precision == 1 {
+                temperature_swar(temperature_word(contents, temp_start), temp_end - temp_start)
             } else {
-                offset += newline_idx as isize;
-                return offset;
+                validate::parse_temperature(&contents[temp_start..temp_end], precision).ok()
+            };
+            if let Some(temp) = temp {
+                let hash = hash_name_at::<H>(contents, line_start, name.len());
+                let stations = chunk.table.len();
+                match chunk.table.insert_or_update(name, hash, temp) {
+                    Ok(inserted) => {
+                        valid = inserted;
+                        if chunk.table.len() > stations {
+                            chunk.added.push((chunk.lines, start_offset + line_start, name.into()));
+                        }
+                    },
+                    Err(kind) => {
+                        chunk.error = Some(RowError { line: chunk.lines, offset: start_offset + line_start, kind });
+                        break;
+                    }
+                }
             }
+            buf_idx = temp_end + 1;
         }
-    }
-    
-    while bytes_remaining > 0 {
-        unsafe {
-            if *ptr.offset(offset) == b'\n' {
-                return offset;
+
+        if !valid {
+            // slow path, the row is looked at again to find out what is wrong with it
+            let line_end = delimiters.next_newline(line_start);
+            let line = &contents[line_start..line_end];
+            let kind = validate::validate_row(line, precision).expect_err("the fast path rejected a valid row");
+            if policy == ErrorPolicy::Strict {
+                chunk.error = Some(RowError { line: chunk.lines, offset: start_offset + line_start, kind });
+                break;
             }
-            offset +=1;
-            bytes_remaining -= 1;
+            chunk.rejected.add(&kind, line, policy);
+            buf_idx = line_end + 1;
         }
     }
-
-    assert!(false, "unreacheable code");
-    0
 }
 
-fn find_next_newline(ptr: *const u8, max_offset: isize, c: &mut Cursor) -> isize {
-    let mut offset = 0;
-    let mut bytes_remaining: isize = max_offset-offset+1;
-    while bytes_remaining > 0 {
-        unsafe {
-            let byte = *ptr.offset(offset);
-            if byte == b'\n' {
-                return offset;
-            }
-            update_temprature(c, byte);
-            offset +=1;
-            bytes_remaining -= 1;
-        }
-    }
 
-    assert!(false, "unreacheable code");
-    0
+// the precision every worker uses, detected from the start of the input if not given
+pub(crate) fn resolve_precision(contents: &[u8], precision: Option<u32>) -> Result<u32, Error> {
+    let precision = precision.unwrap_or_else(|| validate::detect_precision(contents));
+    if !(1..=MAX_PRECISION).contains(&precision) {
+        return Err(Error::InvalidPrecision(precision));
+    }
+    Ok(precision)
 }
 
+// Maps the whole file at `path` into memory.
+fn map_file(path: &str) -> Result<Mmap, Error> {
+    let file = File::open(path)?;
+    unsafe { Mmap::map(&file) }.map_err(Error::Mmap)
+}
 
-fn find_semicolon(data: usize) -> i32 {
-    // finds the 
-    let x = data ^ 0x3B3B3B3B3B3B3B3B;
-    let y = (x-0x0101010101010101) & (!x) & (0x8080808080808080);
-    if y == 0 {
-        return -1;
-    }
-    return (y.trailing_zeros() >> 3) as i32;
+/// Aggregates several files in one go, the workers claim ranges of all of them, see
+/// `schedule::distribute_work`. A file that cannot be mapped only fails its own result.
+pub fn run_files(paths: &[&str], thread_count: usize, policy: ErrorPolicy, precision: Option<u32>) -> Vec<Result<(Results, Rejected), Error>> {
+    let files: Vec<Result<Mmap, Error>> = paths.iter().map(|path| map_file(path)).collect();
+    let inputs: Vec<&[u8]> = files.iter().filter_map(|file| file.as_deref().ok()).collect();
+    let mut outcomes = schedule::run(&inputs, thread_count, policy, precision).into_iter();
+    files.into_iter().map(|file| {
+        file.and_then(|_| outcomes.next().expect("one outcome per mapped file"))
+    }).collect()
 }
 
-fn find_next_semicolon_vectorized(ptr: *const u8, max_offset: isize, hash: &mut usize, name: &mut [u8; 100]) -> isize {
-    let mut offset: isize = 0;
-    let mut bytes_remaining: isize = max_offset-offset+1;
-    while bytes_remaining >= 8 {
-        unsafe {
-            let mut data: usize = read_unaligned_u64(ptr.offset(offset) as *const usize);
-            let sc_idx = find_semicolon(data);
-            if sc_idx == -1 {
-                ptr::copy_nonoverlapping(&data as *const usize as *const u8, name.as_mut_ptr().offset(offset), 8);
-                offset += 8;
-                bytes_remaining -= 8;
-                *hash = (*hash << 5) + *hash + data; 
-            } else {
-                assert!(sc_idx >= 0 && sc_idx < 8);
-                ptr::copy_nonoverlapping(&data as *const usize as *const u8, name.as_mut_ptr().offset(offset), sc_idx as usize);
-                data = !(0xFFFFFFFFFFFFFFFF << 8*sc_idx) & data;
-                *hash = (*hash << 5) + *hash + data; 
-                offset += sc_idx as isize;
-                return offset;
-            }
-        }
-    }
-    
-    while bytes_remaining > 0 {
-        unsafe {
-            let byte = *ptr.offset(offset);
-            if byte == b';' {
-                return offset;
+pub(crate) fn merge_table(result: &mut BTreeMap<String, KeyedStat>, table: &LPTable, precision: u32) {
+    for (key, entry) in table.iter() {
+        let maybe_stat = result.get_mut(key);
+        match maybe_stat {
+            None => {
+                result.insert(String::from(key), entry.to_keyed_stat(&table.names, precision));
+            },
+            Some(stat) => {
+                stat.sum += entry.sum;
+                stat.count += entry.count;
+                stat.min = stat.min.min(entry.min);
+                stat.max = stat.max.max(entry.max);
             }
-            name[offset as usize] = byte;
-            *hash = (*hash << 5) + *hash + byte as usize; 
-            offset +=1;
-            bytes_remaining -= 1;
-        }
+        } 
     }
+}
+
 
-    assert!(false, "unreacheable code");
-    0
+/// Aggregates the file at `path` with workers claiming ranges of it as they go, see
+/// `schedule::distribute_work`.
+pub fn run_with_options(path: &str, thread_count: usize, policy: ErrorPolicy, precision: Option<u32>) -> Result<(BTreeMap<String, KeyedStat>, Rejected), Error> {
+    run_files(&[path], thread_count, policy, precision).remove(0)
 }
 
+/// Fails if the file cannot be read or on the first malformed row.
+pub fn run(path: &str, thread_count: usize) -> Result<BTreeMap<String, KeyedStat>, Error> {
+    run_with_options(path, thread_count, ErrorPolicy::Strict, None).map(|(results, _)| results)
+}
 
-fn compute(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: isize, file_size: usize) -> LPTable {
-    let start_time = Instant::now();
+pub struct Attempt10;
 
-    let file = File::open(filepath).unwrap();
-    let contents = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file).unwrap() };
-    let mut table = LPTable::new(130712);
+impl Solver for Attempt10 {
+    fn name(&self) -> &'static str {
+        "attempt10"
+    }
 
+    fn description(&self) -> &'static str {
+        "SIMD delimiter scanning into an open addressing hash table, ranges claimed as the threads go"
+    }
 
-    let buf = contents.as_ptr();
-    let mut buf_idx: isize = 0;
-    let max_buf_idx: isize = (file_size-start_offset - 1) as isize;
+    fn supports_precision(&self) -> bool {
+        true
+    }
 
+    fn supports_error_policies(&self) -> bool {
+        true
+    }
 
-    let ignore_first_line = thread_id != 0;
-    if ignore_first_line {  
-        unsafe {    
-            let offset = find_next_newline_vectorized(buf.offset(buf_idx) as *const u8, (max_buf_idx-buf_idx));
-            buf_idx += offset as isize + 1;
-        }
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e))
     }
 
-    let mut c = Cursor::new();
-    while true {
-        if buf_idx > file_size_per_thread || buf_idx > max_buf_idx {
-            break;
-        }
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        run_with_options(input, config.thread_count, config.policy, config.precision)
+    }
 
-        let mut station_name_len = 0;
-        unsafe {
-            station_name_len = find_next_semicolon_vectorized(buf.offset(buf_idx) as *const u8, (max_buf_idx-buf_idx), &mut c.hash, &mut c.station);
-        }
-        buf_idx +=  station_name_len + 1;
+    fn try_solve_files(&self, inputs: &[&str], config: &SolverConfig) -> Vec<Result<(Results, Rejected), Error>> {
+        run_files(inputs, config.thread_count, config.policy, config.precision)
+    }
+}
 
-        unsafe {
-            buf_idx += find_next_newline(buf.offset(buf_idx), (max_buf_idx-buf_idx), &mut c);
-        }
-        buf_idx +=1;
 
-        let temp = temprature(&mut c);
-        // println!("Found {}:{}", std::str::from_utf8(&c.station[0..station_name_len as usize]).unwrap(), temp);
-        table.insert_or_update(&c.station, station_name_len as usize, c.hash, temp);  
-        reset(&mut c);
+#[cfg(test)]
+fn aggregate(contents: &[u8], thread_count: usize, policy: ErrorPolicy, precision: Option<u32>) -> Result<(Results, Rejected), Error> {
+    schedule::run(&[contents], thread_count, policy, precision).remove(0)
+}
+
+#[test]
+fn test_impl() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
     }
+    let _ = env_logger::try_init();
 
-    let end_time = Instant::now();
-    info!("Insert Count = {}, Collision count = {}", table.insert_count, table.collision_count);
-    info!("Time taken to compute the stats: {} milliseconds", (end_time - start_time).as_millis());
-    table
+    crate::differential::assert_agrees(&Attempt10, "data/test_small.csv", 1);
 }
 
 
-pub fn distribute_work(path: &'static str, thread_count: usize) -> Vec<JoinHandle<LPTable>> {
-    let mut handles = Vec::with_capacity(thread_count);
-    let file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
-    let file_size_per_thread = file_size.div_ceil(thread_count);
-    for thread_id in 0..thread_count {
-        handles.push(thread::spawn(move || {
-            compute(thread_id, path, file_size_per_thread * thread_id, file_size_per_thread as isize, file_size)
-        }));
+#[test]
+fn test_temperature_swar() {
+    let parse = |field: &str, rest: &str| {
+        let line = format!("{}{}", field, rest);
+        temperature_swar(temperature_word(line.as_bytes(), 0), field.len())
+    };
+    for tenths in -999..=999i32 {
+        let sign = if tenths < 0 { "-" } else { "" };
+        let field = format!("{}{}.{}", sign, tenths.abs() / 10, tenths.abs() % 10);
+        for rest in ["", "\n", "\nHamburg;12.0\n", "\n-1.0\n"] {
+            assert_eq!(parse(&field, rest), Some(tenths), "{:?}", field);
+        }
+        if tenths.abs() < 100 {
+            let padded = format!("{}0{}.{}", sign, tenths.abs() / 10, tenths.abs() % 10);
+            assert_eq!(parse(&padded, "\n"), Some(tenths), "{:?}", padded);
+        }
     }
-    handles
-}
 
-pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, KeyedStat> {
-    let mut result: BTreeMap<String, KeyedStat> = BTreeMap::new();
-    for handle in handles {
-        let lptable = handle.join().unwrap();
-        for ks in lptable.table {
-            if ks.len == 0 {
-                continue;
+    // agrees with the generic parser on every field of up to 5 bytes close to a valid one
+    let alphabet = [b'-', b'.', b'0', b'7', b'9', b'/', b':', b' ', b'\n', 0x80 | b'5'];
+    let mut field = Vec::new();
+    for len in 0..=5u32 {
+        for mut idx in 0..alphabet.len().pow(len) {
+            field.clear();
+            for _ in 0..len {
+                field.push(alphabet[idx % alphabet.len()]);
+                idx /= alphabet.len();
             }
-
-            let key: &str;
-            unsafe {
-                key = str::from_utf8_unchecked(&ks.station[0..ks.len]);
+            let expected = validate::parse_temperature(&field, 1).ok();
+            for rest in [&b""[..], b"\n", b".5.5.5.5"] {
+                let line = [&field[..], rest].concat();
+                assert_eq!(temperature_swar(temperature_word(&line, 0), field.len()), expected, "{:?}", String::from_utf8_lossy(&field));
             }
-            let maybe_stat = result.get_mut(key);
-            match maybe_stat {
-                None => {
-                    result.insert(String::from(key), ks.clone());
-                },
-                Some(stat) => {
-                    stat.sum += ks.sum;
-                    stat.count += ks.count;
-                    stat.min = stat.min.min(ks.min);
-                    stat.max = stat.max.max(ks.max);
-                }
-            } 
         }
     }
-    result
+}
+
+#[test]
+fn test_hash_name_at() {
+    let sf_1 = hash_name_at::<crate::hash::DefaultHash>(b"san_francisco;100", 0, 13);
+    let chicago = hash_name_at::<crate::hash::DefaultHash>(b"chicago;100", 0, 7);
+    let sf_2 = hash_name_at::<crate::hash::DefaultHash>(b"x\nsan_francisco;111", 2, 13);
+    assert_ne!(sf_1, chicago);
+    assert_eq!(sf_1, sf_2);
+
+    // the name hashes the same whether its last word is read from the input or, at
+    // the very end of it, copied byte by byte
+    for name in ["", "a", "chicago", "san_fran", "san_francisco", "a station name of 31 characters"] {
+        let expected = crate::hash::hash_name::<crate::hash::DefaultHash>(name.as_bytes());
+        assert_eq!(hash_name_at::<crate::hash::DefaultHash>(format!("{};1.0", name).as_bytes(), 0, name.len()), expected);
+        assert_eq!(hash_name_at::<crate::hash::DefaultHash>(name.as_bytes(), 0, name.len()), expected, "{:?}", name);
+    }
 }
 
 
-pub fn run(path: &'static str, thread_count: usize) -> BTreeMap<String, KeyedStat> {
-    let handles = distribute_work(path, thread_count);
-    let result = aggregate_result(handles);
-    print_result_btreemap_kstat(&result);
-    result
+#[test]
+fn test_exact_sums() {
+    // 0.1 is not representable in binary floating point, a f32 running sum drifts
+    // well before a million rows.
+    let rows = 1_000_000;
+    let contents = "Hamburg;0.1\nBulawayo;-0.3\n".repeat(rows);
+    let (result, _) = aggregate(contents.as_bytes(), 4, ErrorPolicy::Strict, None).unwrap();
+
+    let hamburg = result.get("Hamburg").unwrap();
+    assert_eq!(hamburg.count, rows as u64);
+    assert_eq!(hamburg.sum, rows as i64);
+    assert_eq!((hamburg.min, hamburg.max), (1, 1));
+
+    let bulawayo = result.get("Bulawayo").unwrap();
+    assert_eq!(bulawayo.sum, -3 * rows as i64);
+    assert_eq!(bulawayo.mean(), -0.3);
 }
 
 
 #[test]
-fn test_impl() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
+fn test_error_policy() {
+    let contents = "# header\nHamburg;12.0\n\nBulawayo;8.9\nNo semicolon\nPalembang;138.2\nHamburg;-1.0\nSt. John's;15.2\n";
+    for thread_count in [1, 2, 5] {
+        let error = aggregate(contents.as_bytes(), thread_count, ErrorPolicy::Strict, None).unwrap_err();
+        assert!(matches!(error, Error::Parse(_)));
+        assert_eq!(error.row(), Some(&RowError { line: 1, offset: 0, kind: validate::RowErrorKind::Comment }));
+
+        let (result, rejected) = aggregate(contents.as_bytes(), thread_count, ErrorPolicy::Skip, None).unwrap();
+        assert_eq!(result.keys().collect::<Vec<_>>(), ["Bulawayo", "Hamburg", "St. John's"]);
+        assert_eq!((result["Hamburg"].sum, result["Hamburg"].count), (110, 2));
+        assert_eq!(rejected.to_string(), "4 rows rejected (1 comment, 1 empty line, 1 missing semicolon, 1 temperature out of range)");
+        assert!(rejected.lines.is_empty());
+
+        let (_, rejected) = aggregate(contents.as_bytes(), thread_count, ErrorPolicy::Quarantine, None).unwrap();
+        assert_eq!(rejected.lines, b"# header\n\nNo semicolon\nPalembang;138.2\n");
     }
-    env_logger::init();
 
-    let path = "data/test_small.csv";
-    let expected = attempt1::naive_btree_kstat(path);
-    let actual = run(path, 1);
-    for (station, ks) in expected.into_iter() {
-        let acs = actual.get(&station).unwrap();
-        assert_eq!(ks.max, acs.max);
-        assert_eq!(ks.min, acs.min);
-        assert_eq!(ks.sum/ks.count, acs.sum/acs.count);
-    }
+    let contents = "Hamburg;12.0\nBulawayo;8.9\nBad;1.23\n";
+    let error = aggregate(contents.as_bytes(), 2, ErrorPolicy::Strict, Some(1)).unwrap_err();
+    assert_eq!(error.to_string(), "line 3 (byte offset 26): temperature has 2 fractional digits, expected 1");
+
+    let long_name = format!("{};1.0\nHamburg;12.0\nX;1.0\n", "a".repeat(101));
+    let mut contents = long_name.into_bytes();
+    let invalid_utf8 = contents.len() - 6;
+    contents[invalid_utf8] = 0xff;
+    let (result, rejected) = aggregate(&contents, 1, ErrorPolicy::Skip, None).unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(rejected.to_string(), "2 rows rejected (1 invalid UTF-8, 1 name too long)");
 }
 
 
+#[test]
+fn test_precision() {
+    let contents = "Tokyo;35.6897\nJakarta;-6.1750\nTokyo;35.68\nJakarta;-6.5\n";
+    for thread_count in [1, 3] {
+        let (result, _) = aggregate(contents.as_bytes(), thread_count, ErrorPolicy::Strict, None).unwrap();
+        let tokyo = result.get("Tokyo").unwrap();
+        assert_eq!((tokyo.min, tokyo.max, tokyo.sum, tokyo.precision), (356800, 356897, 713697, 4));
+        assert_eq!(tokyo.mean_scaled(), 356849);
+        let jakarta = result.get("Jakarta").unwrap();
+        assert_eq!((jakarta.min, jakarta.max, jakarta.sum), (-65000, -61750, -126750));
+    }
+
+    let error = aggregate(contents.as_bytes(), 1, ErrorPolicy::Strict, Some(2)).unwrap_err();
+    assert_eq!(error.to_string(), "line 1 (byte offset 0): temperature has 4 fractional digits, expected 1 to 2");
+}
+
+#[test]
+fn test_run_files() {
+    // only the result of the file with a comment fails, its temperature with 2
+    // decimals does not change the precision of the other files
+    let malformed = std::env::temp_dir().join(format!("onebrc-run-files-{}", std::process::id()));
+    std::fs::write(&malformed, "Hamburg;12.0\n# comment\nBulawayo;8.95\n").unwrap();
+    let paths = ["data/test_small.csv", malformed.to_str().unwrap(), "data/samples/measurements-1.txt"];
+    let expected = |path, policy| run_with_options(path, 1, policy, None).unwrap();
+    for thread_count in [1, 2, 5] {
+        let outcomes = run_files(&paths, thread_count, ErrorPolicy::Strict, None);
+        assert_eq!(outcomes[0].as_ref().unwrap().0, expected(paths[0], ErrorPolicy::Strict).0);
+        assert!(outcomes[0].as_ref().unwrap().0.values().all(|stat| stat.precision == 1));
+        assert_eq!(outcomes[1].as_ref().unwrap_err().to_string(), "line 2 (byte offset 13): comment line");
+        assert_eq!(outcomes[2].as_ref().unwrap().0, expected(paths[2], ErrorPolicy::Strict).0);
+
+        let outcomes = run_files(&paths, thread_count, ErrorPolicy::Skip, None);
+        assert_eq!(outcomes[1].as_ref().unwrap(), &expected(paths[1], ErrorPolicy::Skip));
+        assert!(outcomes[1].as_ref().unwrap().0.values().all(|stat| stat.precision == 2));
+    }
+    std::fs::remove_file(malformed).unwrap();
+}
 
 #[test]
-fn test_find_next_semicolon() {
-    let mut station: [u8; 100] = [0;100];
-
-    let mut sf_hash_1 = 0;
-    let bytes = "san_francisco;100";
-    let offset = 13;
-    assert_eq!(find_next_semicolon_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize, &mut sf_hash_1, &mut station), offset);
-    assert_eq!(&station[0..offset as usize], "san_francisco".as_bytes());
-
-    let mut chicago_hash: usize = 0;
-    let bytes = "chicago;100";
-    let offset = 7;
-    assert_eq!(find_next_semicolon_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize, &mut chicago_hash, &mut station), offset);
-    assert_eq!(&station[0..offset as usize], "chicago".as_bytes());
-
-    let mut sf_hash_2 = 0;
-    let bytes = "san_francisco;111";
-    let offset = 13;
-    assert_eq!(find_next_semicolon_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize, &mut sf_hash_2, &mut station), offset);
-    assert_eq!(&station[0..offset as usize], "san_francisco".as_bytes());
-
-    assert_ne!(sf_hash_1, chicago_hash);
-    assert_eq!(sf_hash_1, sf_hash_2);
+fn test_end_of_input() {
+    let path = std::env::temp_dir().join(format!("onebrc-end-of-input-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    // an empty input, a last line without newline, and inputs with fewer bytes or lines
+    // than threads
+    for contents in ["", "a;1.0", "a;1.0\n", "ab;1.0\nc;-2.5", "Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4"] {
+        let expected = crate::differential::oracle(contents.as_bytes(), 1).0;
+        std::fs::write(path, contents).unwrap();
+        for thread_count in [1, 2, 8, 64] {
+            let context = format!("{:?} with {} threads", contents, thread_count);
+            assert_eq!(run(path, thread_count).unwrap(), expected, "{}", context);
+            assert_eq!(aggregate(contents.as_bytes(), thread_count, ErrorPolicy::Strict, Some(1)).unwrap().0, expected, "{}", context);
+            assert_eq!(crate::aggregate_reader(contents.as_bytes(), thread_count).unwrap(), expected, "{}", context);
+        }
+    }
+    std::fs::remove_file(path).unwrap();
 }
 
+#[test]
+fn test_io_errors() {
+    let missing = "data/no-such-file.txt";
+    assert!(matches!(run(missing, 2), Err(Error::Io(_))));
+    // a directory opens, but cannot be mapped
+    assert!(matches!(run("data", 2), Err(Error::Mmap(_))));
+
+    // only the results of the files that cannot be read fail
+    let outcomes = run_files(&["data/test_small.csv", missing, "data"], 2, ErrorPolicy::Strict, None);
+    assert_eq!(outcomes[0].as_ref().unwrap().0, run("data/test_small.csv", 1).unwrap());
+    assert!(matches!(outcomes[1], Err(Error::Io(_))));
+    assert!(matches!(outcomes[2], Err(Error::Mmap(_))));
+
+    struct FailingHash;
+    impl NameHash for FailingHash {
+        const NAME: &'static str = "failing";
+        const SEED: usize = 0;
+        fn write(_: usize, _: usize) -> usize {
+            panic!("worker failed")
+        }
+    }
+    let error = schedule::distribute_work::<FailingHash>(&[b"Hamburg;12.0\n"], 2, ErrorPolicy::Strict, None, 64).err().unwrap();
+    assert!(matches!(&error, Error::WorkerPanic(message) if message == "worker failed"));
+}
 
 #[test]
-fn test_find_next_newline() {
-    let bytes = "1111111\n";
-    assert_eq!(find_next_newline_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize), 7);
+fn test_lptable() {
+    let mut table = LPTable::new(5);
+    assert_eq!(table.capacity(), 8);
+
+    // all in the last slot, probing wraps around to the start
+    let hash = (0..).find(|&hash| table.home_slot(hash) == 7).unwrap();
+    for (idx, name) in ["a", "b", "c"].iter().enumerate() {
+        assert_eq!(table.insert_or_update(name.as_bytes(), hash, idx as i32), Ok(true));
+    }
+    assert_eq!(table.insert_or_update(b"b", hash, -5), Ok(true));
+    assert_eq!((table.len(), table.capacity()), (3, 8));
+    assert_eq!(table.collision_count(), 1 + 2 + 1);
+
+    // grows once more than half full, keeping the stations
+    for idx in 0..100 {
+        let name = format!("station {}", idx);
+        assert_eq!(table.insert_or_update(name.as_bytes(), idx * 31, 10), Ok(true));
+        assert!(table.load_factor() <= 0.5);
+    }
+    assert_eq!((table.len(), table.capacity()), (103, 256));
+    let (_, b) = table.iter().find(|(name, _)| *name == "b").unwrap();
+    assert_eq!((b.min, b.max, b.count), (-5, 1, 2));
+    assert_eq!(table.insert_or_update("\u{ff}".as_bytes(), 1, 0), Ok(true));
+    assert_eq!(table.insert_or_update(&[0xff], 1, 0), Ok(false));
+
+    table.clear();
+    assert!(table.is_empty());
+    assert_eq!(table.iter().count(), 0);
+}
 
-    let bytes = "11\n11111";
-    assert_eq!(find_next_newline_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize), 2);
+#[test]
+fn test_too_many_stations() {
+    let mut contents = String::new();
+    for idx in 0..MAX_STATIONS {
+        contents.push_str(&format!("s{};1.0\ns{};2.0\n", idx, idx));
+    }
+    let result = aggregate(contents.as_bytes(), 1, ErrorPolicy::Strict, None).unwrap().0;
+    assert_eq!(result.len(), MAX_STATIONS);
 
-    let bytes = "0000000011\n11111";
-    assert_eq!(find_next_newline_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize), 10);
+    contents.push_str("Hamburg;12.0\n");
+    for policy in [ErrorPolicy::Strict, ErrorPolicy::Skip] {
+        let Error::CapacityExceeded(error) = aggregate(contents.as_bytes(), 1, policy, None).unwrap_err() else {
+            panic!("a full table is not a parse error");
+        };
+        assert_eq!(error.line, 2 * MAX_STATIONS + 1);
+        assert_eq!(error.kind, RowErrorKind::TooManyStations(MAX_STATIONS));
+    }
 
-    let bytes = "000000001111111122\n";
-    assert_eq!(find_next_newline_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize), 18);
+    // with several workers none of the tables gets full, the limit is on the input
+    let offset = contents.len() - "Hamburg;12.0\n".len();
+    for (thread_count, chunk_size) in [(2, 1 << 20), (3, 4096), (8, 1000), (64, 100)] {
+        let workers = schedule::distribute_work::<crate::hash::DefaultHash>(&[contents.as_bytes()], thread_count, ErrorPolicy::Skip, None, chunk_size).unwrap();
+        let Err(Error::CapacityExceeded(error)) = schedule::merge_workers(workers, 1).remove(0) else {
+            panic!("more than {} stations with {} threads", MAX_STATIONS, thread_count);
+        };
+        assert_eq!((error.line, error.offset), (2 * MAX_STATIONS + 1, offset), "{} threads", thread_count);
+    }
+    // the limit applies to each input on its own
+    let half = contents.find("s5000;").unwrap();
+    let outcomes = schedule::run(&[&contents.as_bytes()[..half], &contents.as_bytes()[half..]], 4, ErrorPolicy::Strict, None);
+    assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
 }
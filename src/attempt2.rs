@@ -1,6 +1,10 @@
-use std::{cmp::{min}, collections::HashMap, fs::{self, File}, hash::Hash, sync::Arc, thread::{self, JoinHandle}, time::Instant};
+use std::{cmp::{min}, collections::HashMap, fs::{self}, io, sync::Arc, thread::{self}, time::Instant};
 
-use crate::utils::{Stat, LINE_COUNT, THREAD_COUNT, print_result_hashmap};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::{parse_tenths, to_btreemap_kstat, Stat, LINE_COUNT};
+use crate::validate::Rejected;
+use log::info;
 
 // Attempt 2 - Parallelized Stat Computation with Multi-Threading
 // Drawbacks:
@@ -11,9 +15,9 @@ use crate::utils::{Stat, LINE_COUNT, THREAD_COUNT, print_result_hashmap};
 // 3. Suboptimal parsing and hashing: The default methods used for hashing and float-to-string
 //    parsing may not be the most efficient choices.
 // 4. Performance: Execution time ranges from approximately 50 to 60 seconds.
-fn process_file_part<'a>(thread_id: usize, contents: Arc<String>) -> HashMap<String, Stat> {
+fn process_file_part(thread_id: usize, thread_count: usize, contents: Arc<String>) -> HashMap<String, Stat> {
     let mut table: HashMap<String, Stat> = HashMap::new();
-    let lines_per_thread = LINE_COUNT.div_ceil(THREAD_COUNT);
+    let lines_per_thread = LINE_COUNT.div_ceil(thread_count);
     let start_idx = thread_id * lines_per_thread;
     let end_idx = min(start_idx+lines_per_thread, LINE_COUNT);
 
@@ -27,20 +31,20 @@ fn process_file_part<'a>(thread_id: usize, contents: Arc<String>) -> HashMap<Str
         }
         
         if let Some((station, stemp)) = line.split_once(';') {
-            let temp: f32 = stemp.parse().unwrap();
+            let temp = parse_tenths(stemp);
             let maybe_stat = table.get_mut(station);
             match maybe_stat {
                 None => {
                     table.insert(String::from(station), Stat {
                         min: temp, 
                         max: temp, 
-                        count: 1.0, 
-                        sum: temp,
+                        count: 1, 
+                        sum: temp as i64,
                     });
                 }, 
                 Some(stat) => {
-                    stat.sum += temp;
-                    stat.count += 1.0;
+                    stat.sum += temp as i64;
+                    stat.count += 1;
                     stat.min = temp.min(stat.min);
                     stat.max = temp.max(stat.max);
                 }
@@ -50,28 +54,28 @@ fn process_file_part<'a>(thread_id: usize, contents: Arc<String>) -> HashMap<Str
     table
 }
 
-fn read_file(filepath: &str) -> String {
+fn read_file(filepath: &str) -> io::Result<String> {
     let t1: Instant = Instant::now();
-    let contents = fs::read_to_string(filepath).unwrap();
+    let contents = fs::read_to_string(filepath)?;
     let t2: Instant = Instant::now();
-    println!("Time taken to read the file: {} milliseconds", (t2-t1).as_millis());
-    contents
+    info!("Time taken to read the file: {} milliseconds", (t2-t1).as_millis());
+    Ok(contents)
 }
 
-fn compute(thread_count: usize, contents: Arc<String>) -> HashMap<String, Stat> {
+fn compute(thread_count: usize, contents: Arc<String>) -> Result<HashMap<String, Stat>, Error> {
     let t1: Instant = Instant::now();
 
     let mut handles = Vec::with_capacity(thread_count);
     for thread_id in 0..thread_count {
         let thread_contents = contents.clone();
         handles.push(thread::spawn(move || {
-            process_file_part(thread_id, thread_contents)
+            process_file_part(thread_id, thread_count, thread_contents)
         }));
     }
 
     let mut result: HashMap<String, Stat> = HashMap::new(); 
     for handle in handles {
-        let partial_res = handle.join().unwrap();
+        let partial_res = handle.join().map_err(Error::worker_panic)?;
         for (k, v) in partial_res {
             let maybe_stat = result.get_mut(&k);
             match maybe_stat {
@@ -88,14 +92,31 @@ fn compute(thread_count: usize, contents: Arc<String>) -> HashMap<String, Stat>
         }
     }
     let t2: Instant = Instant::now();
-    println!("Time taken to compute the stats: {} milliseconds", (t2-t1).as_millis());
-    result
+    info!("Time taken to compute the stats: {} milliseconds", (t2-t1).as_millis());
+    Ok(result)
 }
 
-pub fn run() {
-    let path = "data/measurements.txt";
-    
-    let contents = Arc::new(read_file(path));
-    let result = compute(THREAD_COUNT, contents);
-    print_result_hashmap(&result);
-}
\ No newline at end of file
+pub fn run(path: &str, thread_count: usize) -> Result<HashMap<String, Stat>, Error> {
+    let contents = Arc::new(read_file(path)?);
+    compute(thread_count, contents)
+}
+
+pub struct Attempt2;
+
+impl Solver for Attempt2 {
+    fn name(&self) -> &'static str {
+        "attempt2"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reads the file on one thread, aggregates line ranges on many threads into HashMaps"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        to_btreemap_kstat(run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e)))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((to_btreemap_kstat(run(input, config.thread_count)?), Rejected::default()))
+    }
+}
@@ -1,6 +1,9 @@
-use std::{ collections::HashMap, fs::{self, File}, io::{Read, Seek, SeekFrom}, thread::{self}, time::Instant};
+use std::{ collections::HashMap, fs::{self, File}, io::{self, Read, Seek, SeekFrom}, thread::{self}, time::Instant};
 
-use crate::utils::{Stat, MAX_LINE_SIZE, THREAD_COUNT, print_result_hashmap};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::{parse_tenths, to_btreemap_kstat, Stat, MAX_LINE_SIZE};
+use crate::validate::Rejected;
 use log::info;
 
 // Attempt 3 - Enhanced Parallel Processing and Memory Management
@@ -11,29 +14,39 @@ use log::info;
 // 2. Optimized looping: Threads now only process the lines within their assigned file segment,
 //    eliminating the previous busy looping issue.
 // 3. Performance: Significant improvement in execution time, reduced to around 20 seconds.
-fn compute(contents: String) -> HashMap<String, Stat> {
+// Aggregates the lines that start within the first `segment_size` bytes of `contents`.
+// The first line belongs to the previous segment unless `contents` starts the file.
+fn compute(contents: String, ignore_first_line: bool, segment_size: usize) -> HashMap<String, Stat> {
     let t1: Instant = Instant::now();
 
-    let start_idx = contents.find('\n').unwrap();
-    let end_idx = contents.rfind('\n').unwrap();
+    let mut start_idx = 0;
+    if ignore_first_line {
+        start_idx = contents.find('\n').map_or(contents.len(), |idx| idx + 1);
+    }
 
     let mut table: HashMap<String, Stat> = HashMap::new();
-    for line in  contents[start_idx..end_idx+1].lines() {
+    let mut line_start = start_idx;
+    for line in contents[start_idx..].split_inclusive('\n') {
+        if line_start > segment_size {
+            break;
+        }
+        line_start += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
         if let Some((station, stemp)) = line.split_once(';') {
-            let temp: f32 = stemp.parse().unwrap();
+            let temp = parse_tenths(stemp);
             let maybe_stat = table.get_mut(station);
             match maybe_stat {
                 None => {
                     table.insert(String::from(station), Stat {
                         min: temp, 
                         max: temp, 
-                        count: 1.0, 
-                        sum: temp,
+                        count: 1, 
+                        sum: temp as i64,
                     });
                 }, 
                 Some(stat) => {
-                    stat.sum += temp;
-                    stat.count += 1.0;
+                    stat.sum += temp as i64;
+                    stat.count += 1;
                     stat.min = temp.min(stat.min);
                     stat.max = temp.max(stat.max);
                 }
@@ -45,50 +58,47 @@ fn compute(contents: String) -> HashMap<String, Stat> {
     table
 }
 
-fn read_file(filepath: &str, start_offset: usize, size: usize) -> String {
+fn read_file(filepath: &str, start_offset: usize, size: usize) -> io::Result<String> {
     let t1: Instant = Instant::now();
-    let mut file = File::open(filepath).unwrap();
-    let curr_offset = file.seek(SeekFrom::Start(start_offset.try_into().unwrap())).unwrap();
+    let mut file = File::open(filepath)?;
+    let curr_offset = file.seek(SeekFrom::Start(start_offset.try_into().unwrap()))?;
 
     // TODO: get ris of the assert
     assert_eq!(curr_offset, start_offset.try_into().unwrap());
 
     let mut buf =  vec![0u8; size];
-    file.read_exact(&mut buf).unwrap();
+    file.read_exact(&mut buf)?;
     let t2: Instant = Instant::now();
     info!("Time taken to read the file: {} milliseconds", (t2-t1).as_millis());
 
     // TODO: use the unchecked version for performance
     unsafe {
-        String::from_utf8_unchecked(buf)
+        Ok(String::from_utf8_unchecked(buf))
     }
 }
 
-fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, size: usize) -> HashMap<String, Stat>  {
-    let contents = read_file(filepath, start_offset, size);
-    compute(contents)
+// The segment of `segment_size` bytes at `start_offset`, read along with the end of
+// its last line.
+fn thread_run(filepath: &str, thread_id: usize, start_offset: usize, segment_size: usize, file_size: usize) -> Result<HashMap<String, Stat>, Error> {
+    let contents = read_file(filepath, start_offset, (segment_size + MAX_LINE_SIZE).min(file_size - start_offset))?;
+    Ok(compute(contents, thread_id != 0, segment_size))
 }
 
-pub fn run() {
-    let path = "data/measurements.txt";
-    
-    let mut handles = Vec::with_capacity(THREAD_COUNT);
-    let mut file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
-    let file_size_per_thread = file_size/THREAD_COUNT;
-    for thread_id in 0..THREAD_COUNT {
-        let mut size = file_size_per_thread + MAX_LINE_SIZE;
-        if thread_id == THREAD_COUNT -1 {
-            size = file_size;
-        }
+pub fn run(path: &str, thread_count: usize) -> Result<HashMap<String, Stat>, Error> {
+    let mut handles = Vec::with_capacity(thread_count);
+    let file_size: usize = fs::metadata(path)?.len().try_into().unwrap();
+    let file_size_per_thread = file_size.div_ceil(thread_count);
+    // the segments of a small file may run out before the threads do
+    for thread_id in (0..thread_count).take_while(|&thread_id| file_size_per_thread * thread_id < file_size) {
+        let path = path.to_string();
         handles.push(thread::spawn(move || {
-            thread_run(thread_id, path, file_size_per_thread * thread_id, size)
+            thread_run(&path, thread_id, file_size_per_thread * thread_id, file_size_per_thread, file_size)
         }));
-        file_size -= file_size_per_thread;
     } 
 
     let mut result: HashMap<String, Stat> = HashMap::new(); 
     for handle in handles {
-        let partial_res = handle.join().unwrap();
+        let partial_res = handle.join().map_err(Error::worker_panic)??;
         for (k, v) in partial_res {
             let maybe_stat = result.get_mut(&k);
             match maybe_stat {
@@ -104,6 +114,25 @@ pub fn run() {
             }
         }
     }
+    Ok(result)
+}
+
+pub struct Attempt3;
+
+impl Solver for Attempt3 {
+    fn name(&self) -> &'static str {
+        "attempt3"
+    }
 
-    print_result_hashmap(&result);
-}
\ No newline at end of file
+    fn description(&self) -> &'static str {
+        "Every thread reads and aggregates its own segment of the file"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        to_btreemap_kstat(run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e)))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((to_btreemap_kstat(run(input, config.thread_count)?), Rejected::default()))
+    }
+}
@@ -1,7 +1,9 @@
-use core::num;
-use std::{collections::BTreeMap, env, fs::{self, File}, io::{Read, Seek, SeekFrom}, ptr, thread::{self, JoinHandle}, time::Instant};
+use std::{collections::BTreeMap, fs::{self, File}, io::{self, Read, Seek, SeekFrom}, ptr, thread::{self, JoinHandle}, time::Instant};
 
-use crate::{attempt1, utils::{print_result_btreemap_kstat, KeyedStat, MAX_LINE_SIZE, THREAD_COUNT}};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::{KeyedStat, MAX_LINE_SIZE};
+use crate::validate::Rejected;
 use log::{debug, info};
 use std::str;
 
@@ -12,7 +14,7 @@ use std::str;
 // 2. We are iterating over line twice in the compute method, once in the lines() method and another time during the hash computation
 //    float parsing.
 
-struct LPTable {
+pub struct LPTable {
     num_slots: usize,
     table: Vec<Vec<KeyedStat>>,
     size: u32,
@@ -43,7 +45,7 @@ impl LPTable {
         count
     }
 
-    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: f32) {
+    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: i32) {
         let slot = hash & (self.num_slots-1);
         debug!("station={}, hash={}, temp={}", str::from_utf8(&station[0..len]).unwrap(), hash, temp);
 
@@ -55,8 +57,8 @@ impl LPTable {
                 continue;
             }
             // println!("Found multiple entries for {}", str::from_utf8(&station[0..len]).unwrap());
-            ks.sum += temp;
-            ks.count += 1.0;
+            ks.sum += temp as i64;
+            ks.count += 1;
             ks.min = temp.min(ks.min);
             ks.max = temp.max(ks.max);
             return;
@@ -64,10 +66,11 @@ impl LPTable {
         let mut ks = KeyedStat{
             min: temp,
             max: temp,
-            count: 1.0,
-            len: len,
-            sum: temp,
+            count: 1,
+            len,
+            sum: temp as i64,
             station: [0;100],
+            precision: 1,
         };
         unsafe {
             ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), ks.station.len());
@@ -82,10 +85,10 @@ impl LPTable {
 }
 
 #[inline(always)]
-fn reset_temp_vars(temp_int_part: &mut u8, temp_fraction_part: &mut u8, temp_multiplier: &mut f32, parsing_int_part: &mut bool) {
+fn reset_temp_vars(temp_int_part: &mut u8, temp_fraction_part: &mut u8, temp_multiplier: &mut i32, parsing_int_part: &mut bool) {
     *temp_int_part = 0;
     *temp_fraction_part = 0;
-    *temp_multiplier = 1.0;
+    *temp_multiplier = 1;
     *parsing_int_part = true;
 }
 
@@ -100,15 +103,13 @@ fn reset_station_vars(station: &mut [u8; 100], station_idx: &mut usize, hash: &m
 #[inline(always)]
 fn update_station(station: &mut [u8; 100], station_idx: &mut usize, hash: &mut usize, byte: u8) {
     station[*station_idx] = byte;
-    unsafe {
-        *hash = ((*hash << 5) + *hash) + byte as usize; // DJB2 hash
-    }
+    *hash = ((*hash << 5) + *hash) + byte as usize; // DJB2 hash
     *station_idx += 1;
 }
 
 #[inline(always)]
 fn update_temp_vars(byte: u8, temp_int_part: &mut u8, temp_fraction_part: &mut u8, parsing_int_part: bool) {
-    let digit = (byte - b'0');
+    let digit = byte - b'0' ;
     if parsing_int_part {
         *temp_int_part = (*temp_int_part * 10) + digit;
     } else {
@@ -134,19 +135,24 @@ fn compute(contents: &str, thread_id: usize, min_bytes_to_process: usize) -> LPT
 
     let mut temp_int_part: u8 = 0;
     let mut temp_fraction_part: u8 = 0;
-    let mut temp_multiplier: f32 = 1.0;
+    let mut temp_multiplier: i32 = 1;
     let mut parsing_int_part = true;
     let mut bytes_read = 0;
 
     let bytes = contents.bytes();
     let mut iter = bytes.into_iter();
     if ignore_first_line {
-        while let Some(b) = iter.next()  {
+        for b in iter.by_ref()  {
             bytes_read += 1;
             if b == b'\n' {
                 break;
             }
         }
+        // the segment ends within the line it starts in, the lines after it belong to
+        // the next segments
+        if bytes_read > min_bytes_to_process {
+            return table;
+        }
     }
 
     for byte in iter {
@@ -154,7 +160,7 @@ fn compute(contents: &str, thread_id: usize, min_bytes_to_process: usize) -> LPT
 
         match byte {
             b'\n' => {
-                temp = f32::try_from(temp_int_part).unwrap() + f32::from(temp_fraction_part) / 10.0;
+                temp = i32::from(temp_int_part) * 10 + i32::from(temp_fraction_part);
                 table.insert_or_update(&station, station_idx, hash, temp_multiplier * temp);
                 row_count+=1;
                 reset_temp_vars(&mut temp_int_part, &mut temp_fraction_part, &mut temp_multiplier, &mut parsing_int_part);
@@ -167,11 +173,17 @@ fn compute(contents: &str, thread_id: usize, min_bytes_to_process: usize) -> LPT
             },
             b';' => parsing_name = false,
             _ if parsing_name => update_station(&mut station, &mut station_idx, &mut hash, byte),
-            b'-' => temp_multiplier = -1.0,
+            b'-' => temp_multiplier = -1,
             b'.' => parsing_int_part = false,
             _ => update_temp_vars(byte, &mut temp_int_part, &mut temp_fraction_part, parsing_int_part),
         }
     }
+    // the last line of the file may have no newline
+    if !parsing_name {
+        temp = i32::from(temp_int_part) * 10 + i32::from(temp_fraction_part);
+        table.insert_or_update(&station, station_idx, hash, temp_multiplier * temp);
+        row_count += 1;
+    }
 
     let end_time = Instant::now();
     debug!("Processed n row = {}", row_count);
@@ -182,53 +194,55 @@ fn compute(contents: &str, thread_id: usize, min_bytes_to_process: usize) -> LPT
     table
 }
 
-fn read_file(filepath: &str, start_offset: usize, size: usize) -> String {
+fn read_file(filepath: &str, start_offset: usize, size: usize) -> io::Result<String> {
     let t1: Instant = Instant::now();
 
-    let mut file = File::open(filepath).unwrap();
-    let curr_offset = file.seek(SeekFrom::Start(start_offset.try_into().unwrap())).unwrap();
+    let mut file = File::open(filepath)?;
+    let curr_offset = file.seek(SeekFrom::Start(start_offset.try_into().unwrap()))?;
 
     // TODO: get rid of the assert
     assert_eq!(curr_offset, start_offset.try_into().unwrap());
 
     let mut buf =  vec![0u8; size];
-    file.read_exact(&mut buf).unwrap();
+    file.read_exact(&mut buf)?;
     let t2: Instant = Instant::now();
     info!("Time taken to read the file: {} milliseconds", (t2-t1).as_millis());
 
     unsafe {
-        String::from_utf8_unchecked(buf)
+        Ok(String::from_utf8_unchecked(buf))
     }
 }
 
-fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, mut file_size_per_thread: usize) -> LPTable {
-    let file_size: usize = fs::metadata(filepath).unwrap().len().try_into().unwrap();
+fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: usize) -> Result<LPTable, Error> {
+    let file_size: usize = fs::metadata(filepath)?.len().try_into().unwrap();
     let mut bytes_to_read = file_size_per_thread;
     bytes_to_read += MAX_LINE_SIZE;
     let bytes_remaining = file_size - start_offset;
     if bytes_remaining < bytes_to_read {
         bytes_to_read = bytes_remaining;
     }
-    let contents = read_file(filepath, start_offset, bytes_to_read);
-    compute(&contents, thread_id, file_size_per_thread)
+    let contents = read_file(filepath, start_offset, bytes_to_read)?;
+    Ok(compute(&contents, thread_id, file_size_per_thread))
 }
 
-pub fn distribute_work(path: &'static str, thread_count: usize) -> Vec<JoinHandle<LPTable>> {
+pub fn distribute_work(path: &str, thread_count: usize) -> Result<Vec<JoinHandle<Result<LPTable, Error>>>, Error> {
     let mut handles = Vec::with_capacity(thread_count);
-    let file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
+    let file_size: usize = fs::metadata(path)?.len().try_into().unwrap();
     let file_size_per_thread = file_size.div_ceil(thread_count);
-    for thread_id in 0..thread_count {
+    // the segments of a small file may run out before the threads do
+    for thread_id in (0..thread_count).take_while(|&thread_id| file_size_per_thread * thread_id < file_size) {
+        let path = path.to_string();
         handles.push(thread::spawn(move || {
-            thread_run(thread_id, path, file_size_per_thread * thread_id, file_size_per_thread)
+            thread_run(thread_id, &path, file_size_per_thread * thread_id, file_size_per_thread)
         }));
     }
-    handles
+    Ok(handles)
 }
 
-pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, KeyedStat> {
+pub fn aggregate_result(handles: Vec<JoinHandle<Result<LPTable, Error>>>) -> Result<BTreeMap<String, KeyedStat>, Error> {
     let mut result: BTreeMap<String, KeyedStat> = BTreeMap::new();
     for handle in handles {
-        let lptable = handle.join().unwrap();
+        let lptable = handle.join().map_err(Error::worker_panic)??;
         for slot in lptable.occupied_slots {
             for ks in &lptable.table[slot] {
                 let key: &str;
@@ -238,7 +252,7 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
                 let maybe_stat = result.get_mut(key);
                 match maybe_stat {
                     None => {
-                        result.insert(String::from(key), ks.clone());
+                        result.insert(String::from(key), *ks);
                     },
                     Some(stat) => {
                         stat.sum += ks.sum;
@@ -251,29 +265,40 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
             }
         }
     }
-    result
+    Ok(result)
 }
 
-pub fn run(path: &'static str) -> BTreeMap<String, KeyedStat> {
-    let handles = distribute_work(path, 3);
-    let result = aggregate_result(handles);
-    print_result_btreemap_kstat(&result);
-    result
+pub fn run(path: &str, thread_count: usize) -> Result<BTreeMap<String, KeyedStat>, Error> {
+    let handles = distribute_work(path, thread_count)?;
+    aggregate_result(handles)
+}
+
+pub struct Attempt5;
+
+impl Solver for Attempt5 {
+    fn name(&self) -> &'static str {
+        "attempt5"
+    }
+
+    fn description(&self) -> &'static str {
+        "Single pass byte parser into a custom chained hash table"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((run(input, config.thread_count)?, Rejected::default()))
+    }
 }
 
+
 #[test]
 fn test_impl() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
-    }
-    env_logger::init();
-    let path = "data/test_small.csv";
-    let expected = attempt1::naive_btree_kstat(path);
-    let actual = run(path);
-    for (station, ks) in expected.into_iter() {
-        let acs = actual.get(&station).unwrap();
-        assert_eq!(ks.max, acs.max);
-        assert_eq!(ks.min, acs.min);
-        assert_eq!(ks.sum/ks.count, acs.sum/acs.count);
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
     }
+    let _ = env_logger::try_init();
+    crate::differential::assert_agrees(&Attempt5, "data/test_small.csv", 3);
 }
\ No newline at end of file
@@ -1,7 +1,9 @@
-use core::num;
-use std::{collections::BTreeMap, env, fs::{self, File}, io::{Read, Seek, SeekFrom}, ptr, thread::{self, JoinHandle}, time::Instant};
+use std::{collections::BTreeMap, fs::{self, File}, ptr, thread::{self, JoinHandle}, time::Instant};
 
-use crate::{attempt1, utils::{print_result_btreemap_kstat, print_result_hashmap, KeyedStat, Stat, MAX_LINE_SIZE, THREAD_COUNT}};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::KeyedStat;
+use crate::validate::Rejected;
 use log::{debug, info};
 use std::str;
 use memmap2::{Mmap, MmapOptions};
@@ -13,7 +15,7 @@ use memmap2::{Mmap, MmapOptions};
 // 2. We are iterating over line twice in the compute method, once in the lines() method and another time during the hash computation
 //    float parsing.
 
-struct LPTable {
+pub struct LPTable {
     num_slots: usize,
     table: Vec<Vec<KeyedStat>>,
     size: usize,
@@ -43,12 +45,7 @@ impl LPTable {
         count
     }
 
-    fn len(&self) -> usize {
-        self.size
-    }
-
-
-    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: f32) {
+    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: i32) {
         let slot = hash & (self.num_slots-1);
         debug!("station={}, hash={}, temp={}", str::from_utf8(&station[0..len]).unwrap(), hash, temp);
 
@@ -60,8 +57,8 @@ impl LPTable {
                 continue;
             }
             // println!("Found multiple entries for {}", str::from_utf8(&station[0..len]).unwrap());
-            ks.sum += temp;
-            ks.count += 1.0;
+            ks.sum += temp as i64;
+            ks.count += 1;
             ks.min = temp.min(ks.min);
             ks.max = temp.max(ks.max);
             return;
@@ -69,10 +66,11 @@ impl LPTable {
         let mut ks = KeyedStat{
             min: temp,
             max: temp,
-            count: 1.0,
-            len: len,
-            sum: temp,
+            count: 1,
+            len,
+            sum: temp as i64,
             station: [0;100],
+            precision: 1,
         };
         unsafe {
             ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), ks.station.len());
@@ -86,10 +84,10 @@ impl LPTable {
 
 }
 
-fn reset_temp_vars(temp_int_part: &mut u8, temp_fraction_part: &mut u8, temp_multiplier: &mut f32, parsing_int_part: &mut bool) {
+fn reset_temp_vars(temp_int_part: &mut u8, temp_fraction_part: &mut u8, temp_multiplier: &mut i32, parsing_int_part: &mut bool) {
     *temp_int_part = 0;
     *temp_fraction_part = 0;
-    *temp_multiplier = 1.0;
+    *temp_multiplier = 1;
     *parsing_int_part = true;
 }
 
@@ -102,14 +100,12 @@ fn reset_station_vars(station: &mut [u8; 100], station_idx: &mut usize, hash: &m
 
 fn update_station(station: &mut [u8; 100], station_idx: &mut usize, hash: &mut usize, byte: u8) {
     station[*station_idx] = byte;
-    unsafe {
-        *hash = ((*hash << 5) + *hash) + byte as usize; // DJB2 hash
-    }
+    *hash = ((*hash << 5) + *hash) + byte as usize; // DJB2 hash
     *station_idx += 1;
 }
 
 fn update_temp_vars(byte: u8, temp_int_part: &mut u8, temp_fraction_part: &mut u8, parsing_int_part: bool) {
-    let digit = (byte - b'0');
+    let digit = byte - b'0' ;
     if parsing_int_part {
         *temp_int_part = (*temp_int_part * 10) + digit;
     } else {
@@ -118,7 +114,7 @@ fn update_temp_vars(byte: u8, temp_int_part: &mut u8, temp_fraction_part: &mut u
 }
 
 fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -> LPTable {
-    let mut ignore_first_line = thread_id != 0;
+    let ignore_first_line = thread_id != 0;
 
     let start_time = Instant::now();
     let mut table = LPTable::new(130712, 4);
@@ -132,18 +128,23 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
 
     let mut temp_int_part: u8 = 0;
     let mut temp_fraction_part: u8 = 0;
-    let mut temp_multiplier: f32 = 1.0;
+    let mut temp_multiplier: i32 = 1;
     let mut parsing_int_part = true;
     let mut bytes_read = 0;
 
-    let mut iter = contents.into_iter();
+    let mut iter = contents.iter();
     if ignore_first_line {
-        while let Some(&byte) = iter.next() {
+        for &byte in iter.by_ref() {
             bytes_read += 1;
             if byte == b'\n' {
                 break;
             }
         }
+        // the segment ends within the line it starts in, the lines after it belong to
+        // the next segments
+        if bytes_read > min_bytes_to_process {
+            return table;
+        }
     }
     
     for &byte in iter {
@@ -151,7 +152,7 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
 
         match byte {
             b'\n' => {
-                temp = f32::try_from(temp_int_part).unwrap() + f32::from(temp_fraction_part) / 10.0;
+                temp = i32::from(temp_int_part) * 10 + i32::from(temp_fraction_part);
                 table.insert_or_update(&station, station_idx, hash, temp_multiplier * temp);
                 row_count+=1;
                 reset_temp_vars(&mut temp_int_part, &mut temp_fraction_part, &mut temp_multiplier, &mut parsing_int_part);
@@ -162,11 +163,17 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
             },
             b';' => parsing_name = false,
             _ if parsing_name => update_station(&mut station, &mut station_idx, &mut hash, byte),
-            b'-' => temp_multiplier = -1.0,
+            b'-' => temp_multiplier = -1,
             b'.' => parsing_int_part = false,
             _ => update_temp_vars(byte, &mut temp_int_part, &mut temp_fraction_part, parsing_int_part),
         }
     }
+    // the last line of the file may have no newline
+    if !parsing_name {
+        temp = i32::from(temp_int_part) * 10 + i32::from(temp_fraction_part);
+        table.insert_or_update(&station, station_idx, hash, temp_multiplier * temp);
+        row_count += 1;
+    }
 
     let end_time = Instant::now();
     debug!("Processed n row = {}", row_count);
@@ -178,32 +185,32 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
 }
 
 
-fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: usize) -> LPTable {
-    let mut file = File::open(filepath).unwrap();
-    let mut mmap = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file).unwrap() };
-    compute(&mut mmap, thread_id, file_size_per_thread)
+fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: usize) -> Result<LPTable, Error> {
+    let file = File::open(filepath)?;
+    let mut mmap = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file) }.map_err(Error::Mmap)?;
+    Ok(compute(&mut mmap, thread_id, file_size_per_thread))
 }
 
-pub fn distribute_work(path: &'static str, thread_count: usize) -> Vec<JoinHandle<LPTable>> {
+pub fn distribute_work(path: &str, thread_count: usize) -> Result<Vec<JoinHandle<Result<LPTable, Error>>>, Error> {
     let mut handles = Vec::with_capacity(thread_count);
-    let file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
+    let file_size: usize = fs::metadata(path)?.len().try_into().unwrap();
     let file_size_per_thread = file_size.div_ceil(thread_count);
-    for thread_id in 0..thread_count {
+    // the segments of a small file may run out before the threads do
+    for thread_id in (0..thread_count).take_while(|&thread_id| file_size_per_thread * thread_id < file_size) {
+        let path = path.to_string();
         handles.push(thread::spawn(move || {
-            thread_run(thread_id, path, file_size_per_thread * thread_id, file_size_per_thread)
+            thread_run(thread_id, &path, file_size_per_thread * thread_id, file_size_per_thread)
         }));
     }
-    handles
+    Ok(handles)
 }
 
-pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, KeyedStat> {
-    let mut count = 0;
+pub fn aggregate_result(handles: Vec<JoinHandle<Result<LPTable, Error>>>) -> Result<BTreeMap<String, KeyedStat>, Error> {
     let mut result: BTreeMap<String, KeyedStat> = BTreeMap::new();
     for handle in handles {
-        let lptable = handle.join().unwrap();
+        let lptable = handle.join().map_err(Error::worker_panic)??;
         for slot in lptable.occupied_slots {
             for ks in &lptable.table[slot] {
-                count += 1;
                 let key: &str;
                 unsafe {
                     key = str::from_utf8_unchecked(&ks.station[0..ks.len]);
@@ -211,7 +218,7 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
                 let maybe_stat = result.get_mut(key);
                 match maybe_stat {
                     None => {
-                        result.insert(String::from(key), ks.clone());
+                        result.insert(String::from(key), *ks);
                     },
                     Some(stat) => {
                         stat.sum += ks.sum;
@@ -224,32 +231,42 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
             }
         }
     }
-    result
+    Ok(result)
+}
+
+
+pub fn run(path: &str, thread_count: usize) -> Result<BTreeMap<String, KeyedStat>, Error> {
+    let handles = distribute_work(path, thread_count)?;
+    aggregate_result(handles)
 }
 
+pub struct Attempt6;
 
-pub fn run(path: &'static str, thread_count: usize) -> BTreeMap<String, KeyedStat> {
-    let handles = distribute_work(path, thread_count);
-    let result = aggregate_result(handles);
-    print_result_btreemap_kstat(&result);
-    result
+impl Solver for Attempt6 {
+    fn name(&self) -> &'static str {
+        "attempt6"
+    }
+
+    fn description(&self) -> &'static str {
+        "Attempt 5 over memory mapped file segments"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((run(input, config.thread_count)?, Rejected::default()))
+    }
 }
 
 
 #[test]
 fn test_impl() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
-    }
-    env_logger::init();
-
-    let path = "data/test_small.csv";
-    let expected = attempt1::naive_btree_kstat(path);
-    let actual = run(path, 3);
-    for (station, ks) in expected.into_iter() {
-        let acs = actual.get(&station).unwrap();
-        assert_eq!(ks.max, acs.max);
-        assert_eq!(ks.min, acs.min);
-        assert_eq!(ks.sum/ks.count, acs.sum/acs.count);
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
     }
+    let _ = env_logger::try_init();
+
+    crate::differential::assert_agrees(&Attempt6, "data/test_small.csv", 3);
 }
\ No newline at end of file
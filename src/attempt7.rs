@@ -1,7 +1,9 @@
-use core::num;
-use std::{collections::BTreeMap, env, fs::{self, File}, io::{Read, Seek, SeekFrom}, ptr, thread::{self, current, JoinHandle}, time::Instant};
+use std::{collections::BTreeMap, fs::{self, File}, ptr, thread::{self, JoinHandle}, time::Instant};
 
-use crate::{attempt1, utils::{print_result_btreemap_kstat, print_result_hashmap, KeyedStat, Stat, MAX_LINE_SIZE, THREAD_COUNT}};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::KeyedStat;
+use crate::validate::Rejected;
 use log::{debug, info};
 use std::str;
 use memmap2::{Mmap, MmapOptions};
@@ -15,7 +17,7 @@ use memmap2::{Mmap, MmapOptions};
 
 
 
-struct LPTable {
+pub struct LPTable {
     num_slots: usize,
     table: Vec<Vec<KeyedStat>>,
     size: usize,
@@ -37,24 +39,7 @@ impl LPTable {
         }
     }
 
-    fn get_collision_count(&self) -> usize {
-        let mut m = 0;
-        let mut count = 0;
-        for i in 0..self.num_slots {
-            if self.table[i].len() > 1 {
-                count += self.table[i].len();
-            }
-            m = std::cmp::max(self.table[i].len(), m);
-        }
-        count
-    }
-
-    fn len(&self) -> usize {
-        self.size
-    }
-
-
-    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: f32) {
+    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: i32) {
         let slot = hash & (self.num_slots-1);
         self.insert_count += 1;
         debug!("station={}, hash={}, temp={}", str::from_utf8(&station[0..len]).unwrap(), hash, temp);
@@ -68,8 +53,8 @@ impl LPTable {
                 continue;
             }
             // println!("Found multiple entries for {}", str::from_utf8(&station[0..len]).unwrap());
-            ks.sum += temp;
-            ks.count += 1.0;
+            ks.sum += temp as i64;
+            ks.count += 1;
             ks.min = temp.min(ks.min);
             ks.max = temp.max(ks.max);
             return;
@@ -77,10 +62,11 @@ impl LPTable {
         let mut ks = KeyedStat{
             min: temp,
             max: temp,
-            count: 1.0,
-            len: len,
-            sum: temp,
+            count: 1,
+            len,
+            sum: temp as i64,
             station: [0;100],
+            precision: 1,
         };
         unsafe {
             ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), ks.station.len());
@@ -102,27 +88,28 @@ struct Cursor {
     parsing_int_part: bool,
     station_idx: usize,
     hash: usize,
-    temp_multiplier: f32,
+    temp_multiplier: i32,
     station: [u8; 100],
 }
 
 impl Cursor {
     fn new() -> Cursor {
-        return Cursor {
+        Cursor {
             hash: 5381,
             station: [0; 100],
             station_idx: 0,
             parsing_name: true,
             temp_int_part: 0,
             temp_fraction_part: 0,
-            temp_multiplier: 1.0,
+            temp_multiplier: 1,
             parsing_int_part: true
-        };
+        }
     }
 }
 
-fn temprature(c: &mut Cursor) -> f32 {
-    c.temp_multiplier * (f32::try_from(c.temp_int_part).unwrap() + f32::from(c.temp_fraction_part) / 10.0)
+// temperature in tenths of a degree
+fn temprature(c: &mut Cursor) -> i32 {
+    c.temp_multiplier * (i32::from(c.temp_int_part) * 10 + i32::from(c.temp_fraction_part))
 }
     
 fn reset(c: &mut Cursor) {
@@ -132,7 +119,7 @@ fn reset(c: &mut Cursor) {
     c.parsing_name = true;
     c.temp_int_part = 0;
     c.temp_fraction_part = 0;
-    c.temp_multiplier = 1.0;
+    c.temp_multiplier = 1;
     c.parsing_int_part = true;
 }
 
@@ -160,14 +147,19 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
     let mut bytes_read = 0;
     let mut c = Cursor::new();
 
-    let mut iter = contents.into_iter();
+    let mut iter = contents.iter();
     if ignore_first_line {
-        while let Some(&byte) = iter.next() {
+        for &byte in iter.by_ref() {
             bytes_read += 1;
             if byte == b'\n' {
                 break;
             }
         }
+        // the segment ends within the line it starts in, the lines after it belong to
+        // the next segments
+        if bytes_read > min_bytes_to_process {
+            return table;
+        }
     }
     
     for &byte in iter {
@@ -184,11 +176,16 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
             },
             b';' => c.parsing_name = false,
             _ if c.parsing_name => update_station(&mut c, byte),
-            b'-' => c.temp_multiplier = -1.0,
+            b'-' => c.temp_multiplier = -1,
             b'.' => c.parsing_int_part = false,
             _ => update_temprature(&mut c, byte),
         }     
     }
+    // the last line of the file may have no newline
+    if !c.parsing_name {
+        let temprature = temprature(&mut c);
+        table.insert_or_update(&c.station, c.station_idx, c.hash, temprature);
+    }
 
     let end_time = Instant::now();
     info!("Insert count = {}, Collision count = {}", table.insert_count, table.collision_count);
@@ -197,32 +194,32 @@ fn compute(contents: &mut Mmap, thread_id: usize, min_bytes_to_process: usize) -
 }
 
 
-fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: usize) -> LPTable {
-    let mut file = File::open(filepath).unwrap();
-    let mut mmap = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file).unwrap() };
-    compute(&mut mmap, thread_id, file_size_per_thread)
+fn thread_run(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: usize) -> Result<LPTable, Error> {
+    let file = File::open(filepath)?;
+    let mut mmap = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file) }.map_err(Error::Mmap)?;
+    Ok(compute(&mut mmap, thread_id, file_size_per_thread))
 }
 
-pub fn distribute_work(path: &'static str, thread_count: usize) -> Vec<JoinHandle<LPTable>> {
+pub fn distribute_work(path: &str, thread_count: usize) -> Result<Vec<JoinHandle<Result<LPTable, Error>>>, Error> {
     let mut handles = Vec::with_capacity(thread_count);
-    let file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
+    let file_size: usize = fs::metadata(path)?.len().try_into().unwrap();
     let file_size_per_thread = file_size.div_ceil(thread_count);
-    for thread_id in 0..thread_count {
+    // the segments of a small file may run out before the threads do
+    for thread_id in (0..thread_count).take_while(|&thread_id| file_size_per_thread * thread_id < file_size) {
+        let path = path.to_string();
         handles.push(thread::spawn(move || {
-            thread_run(thread_id, path, file_size_per_thread * thread_id, file_size_per_thread)
+            thread_run(thread_id, &path, file_size_per_thread * thread_id, file_size_per_thread)
         }));
     }
-    handles
+    Ok(handles)
 }
 
-pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, KeyedStat> {
-    let mut count = 0;
+pub fn aggregate_result(handles: Vec<JoinHandle<Result<LPTable, Error>>>) -> Result<BTreeMap<String, KeyedStat>, Error> {
     let mut result: BTreeMap<String, KeyedStat> = BTreeMap::new();
     for handle in handles {
-        let lptable = handle.join().unwrap();
+        let lptable = handle.join().map_err(Error::worker_panic)??;
         for slot in lptable.occupied_slots {
             for ks in &lptable.table[slot] {
-                count += 1;
                 let key: &str;
                 unsafe {
                     key = str::from_utf8_unchecked(&ks.station[0..ks.len]);
@@ -230,7 +227,7 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
                 let maybe_stat = result.get_mut(key);
                 match maybe_stat {
                     None => {
-                        result.insert(String::from(key), ks.clone());
+                        result.insert(String::from(key), *ks);
                     },
                     Some(stat) => {
                         stat.sum += ks.sum;
@@ -243,32 +240,42 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
             }
         }
     }
-    result
+    Ok(result)
 }
 
 
-pub fn run(path: &'static str, thread_count: usize) -> BTreeMap<String, KeyedStat> {
-    let handles = distribute_work(path, thread_count);
-    let result = aggregate_result(handles);
-    print_result_btreemap_kstat(&result);
-    result
+pub fn run(path: &str, thread_count: usize) -> Result<BTreeMap<String, KeyedStat>, Error> {
+    let handles = distribute_work(path, thread_count)?;
+    aggregate_result(handles)
+}
+
+pub struct Attempt7;
+
+impl Solver for Attempt7 {
+    fn name(&self) -> &'static str {
+        "attempt7"
+    }
+
+    fn description(&self) -> &'static str {
+        "Attempt 6 with the parser state kept in a Cursor"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((run(input, config.thread_count)?, Rejected::default()))
+    }
 }
 
 
 #[test]
 fn test_impl() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
-    }
-    env_logger::init();
-
-    let path = "data/test_small.csv";
-    let expected = attempt1::naive_btree_kstat(path);
-    let actual = run(path, 3);
-    for (station, ks) in expected.into_iter() {
-        let acs = actual.get(&station).unwrap();
-        assert_eq!(ks.max, acs.max);
-        assert_eq!(ks.min, acs.min);
-        assert_eq!(ks.sum/ks.count, acs.sum/acs.count);
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
     }
+    let _ = env_logger::try_init();
+
+    crate::differential::assert_agrees(&Attempt7, "data/test_small.csv", 3);
 }
\ No newline at end of file
@@ -1,14 +1,15 @@
-use core::num;
-use std::{collections::BTreeMap, env, fs::{self, File}, io::{Read, Seek, SeekFrom}, path::Iter, ptr, sync::{atomic::AtomicUsize, Arc}, thread::{self, current, JoinHandle}, time::Instant};
+use std::{collections::BTreeMap, fs::File, ptr, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread::{self, JoinHandle}, time::Instant};
 
-use crate::{attempt1, utils::{print_result_btreemap_kstat, print_result_hashmap, KeyedStat, Stat, MAX_LINE_SIZE, THREAD_COUNT}};
+use crate::error::Error;
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::utils::KeyedStat;
+use crate::validate::Rejected;
 use log::{debug, info};
 use std::str;
-use memmap2::{Mmap, MmapOptions};
-use std::sync::atomic::{AtomicU64};
+use memmap2::Mmap;
 
 
-struct LPTable {
+pub struct LPTable {
     num_slots: usize,
     table: Vec<Vec<KeyedStat>>,
     size: usize,
@@ -26,24 +27,7 @@ impl LPTable {
         }
     }
 
-    fn get_collision_count(&self) -> usize {
-        let mut m = 0;
-        let mut count = 0;
-        for i in 0..self.num_slots {
-            if self.table[i].len() > 1 {
-                count += self.table[i].len();
-            }
-            m = std::cmp::max(self.table[i].len(), m);
-        }
-        count
-    }
-
-    fn len(&self) -> usize {
-        self.size
-    }
-
-
-    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: f32) {
+    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: i32) {
         let slot = hash & (self.num_slots-1);
         debug!("station={}, hash={}, temp={}", str::from_utf8(&station[0..len]).unwrap(), hash, temp);
 
@@ -55,8 +39,8 @@ impl LPTable {
                 continue;
             }
             // println!("Found multiple entries for {}", str::from_utf8(&station[0..len]).unwrap());
-            ks.sum += temp;
-            ks.count += 1.0;
+            ks.sum += temp as i64;
+            ks.count += 1;
             ks.min = temp.min(ks.min);
             ks.max = temp.max(ks.max);
             return;
@@ -64,10 +48,11 @@ impl LPTable {
         let mut ks = KeyedStat{
             min: temp,
             max: temp,
-            count: 1.0,
-            len: len,
-            sum: temp,
+            count: 1,
+            len,
+            sum: temp as i64,
             station: [0;100],
+            precision: 1,
         };
         unsafe {
             ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), ks.station.len());
@@ -89,28 +74,29 @@ struct Cursor {
     parsing_int_part: bool,
     station_idx: usize,
     hash: usize,
-    temp_multiplier: f32,
+    temp_multiplier: i32,
     station: [u8; 100],
 }
 
 impl Cursor {
     fn new() -> Cursor {
-        return Cursor {
+        Cursor {
             hash: 5381,
             station: [0; 100],
             station_idx: 0,
             parsing_name: true,
             temp_int_part: 0,
             temp_fraction_part: 0,
-            temp_multiplier: 1.0,
+            temp_multiplier: 1,
             parsing_int_part: true, 
-        };
+        }
     }
 
 }
 
-fn temprature(c: &mut Cursor) -> f32 {
-    c.temp_multiplier * (f32::try_from(c.temp_int_part).unwrap() + f32::from(c.temp_fraction_part) / 10.0)
+// temperature in tenths of a degree
+fn temprature(c: &mut Cursor) -> i32 {
+    c.temp_multiplier * (i32::from(c.temp_int_part) * 10 + i32::from(c.temp_fraction_part))
 }
     
 fn reset(c: &mut Cursor) {
@@ -120,7 +106,7 @@ fn reset(c: &mut Cursor) {
     c.parsing_name = true;
     c.temp_int_part = 0;
     c.temp_fraction_part = 0;
-    c.temp_multiplier = 1.0;
+    c.temp_multiplier = 1;
     c.parsing_int_part = true;
 }
 
@@ -140,132 +126,114 @@ fn update_station(c: &mut Cursor, byte: u8) {
 }  
 
 
-fn compute(thread_id: usize, filepath: &str, offset_counter: Arc<AtomicUsize>, file_size: usize) -> LPTable {
-    let start_time: Instant = Instant::now();
-    let min_bytes_to_process = 4 * 1024 * 1024; // 2 MB
-    let file = File::open(filepath).unwrap();
-
-    let mut table_1 = LPTable::new(130712, 2);
-    let mut table_2 = LPTable::new(130712, 2);
+// One of the sub-ranges a worker interleaves. It owns the lines that start in
+// (start, end], and the line at 0 for the first sub-range of the file: the line
+// that starts at or runs across `start` belongs to the sub-range before it.
+struct Stream {
+    cursor: Cursor,
+    pos: usize,
+    end: usize,
+    done: bool,
+}
 
+impl Stream {
+    fn new(contents: &[u8], start: usize, end: usize, ignore_first_line: bool) -> Stream {
+        let pos = match ignore_first_line {
+            false => start,
+            true => contents[start..].iter().position(|&byte| byte == b'\n').map_or(contents.len(), |idx| start + idx + 1),
+        };
+        Stream { cursor: Cursor::new(), pos, end, done: pos > end || pos >= contents.len() }
+    }
 
-    while true {
-        let start_offset = offset_counter.fetch_add(min_bytes_to_process, std::sync::atomic::Ordering::Relaxed);
-        if start_offset >= file_size {
-            break;
+    // consumes one byte
+    #[inline(always)]
+    fn step(&mut self, contents: &[u8], table: &mut LPTable) {
+        let c = &mut self.cursor;
+        match contents[self.pos] {
+            b'\n' => {
+                let temprature = temprature(c);
+                table.insert_or_update(&c.station, c.station_idx, c.hash, temprature);
+                reset(c);
+                // the next line starts after the end of the range
+                self.done = self.pos >= self.end;
+            },
+            b';' => c.parsing_name = false,
+            byte if c.parsing_name => update_station(c, byte),
+            b'-' => c.temp_multiplier = -1,
+            b'.' => c.parsing_int_part = false,
+            byte => update_temprature(c, byte),
         }
-            
-        let mut c_1 = Cursor::new();
-        let mut c_2 = Cursor::new();
-
-        let buffer_1 =  unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file).unwrap() };
-        let buffer_2 =  unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file).unwrap() };
-
-        
-        let mut byte_count_1 = 0;
-        let mut byte_count_2 = 0;
-
-        let  byte_limit_1 =   min_bytes_to_process/2;
-        let  byte_limit_2 = min_bytes_to_process/2;
-
+        self.pos += 1;
+        if self.pos == contents.len() && !self.done {
+            // the last line of the file has no newline
+            if contents[self.pos - 1] != b'\n' {
+                let temprature = temprature(c);
+                table.insert_or_update(&c.station, c.station_idx, c.hash, temprature);
+            }
+            self.done = true;
+        }
+    }
+}
 
-        let mut iter_1 = buffer_1.into_iter();
-        let mut iter_2 = buffer_2.into_iter();
+/// Sub-ranges of a chunk parsed in the same loop, each with its own table.
+pub const STREAMS: usize = 2;
+/// Size of the chunks the threads claim, in bytes.
+pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
 
-        let ignore_first_line_1 = start_offset != 0;
-        let ignore_first_line_2 = true;
+fn compute(contents: &[u8], offset_counter: &AtomicUsize, chunk_size: usize) -> Vec<LPTable> {
+    let start_time: Instant = Instant::now();
+    let mut tables: Vec<LPTable> = (0..STREAMS).map(|_| LPTable::new(130712, 2)).collect();
 
-        if ignore_first_line_1 {
-            while let Some(&byte) = iter_1.next() {
-                byte_count_1 += 1;
-                if byte == b'\n' {
-                    break;
-                }
-            }
+    loop {
+        let start_offset = offset_counter.fetch_add(chunk_size, Ordering::Relaxed);
+        if start_offset >= contents.len() {
+            break;
         }
-
-        if ignore_first_line_2 {
-            while let Some(&byte) = iter_2.next() {
-                byte_count_2 += 1;
-                if byte == b'\n' {
-                    break;
+        let end_offset = (start_offset + chunk_size).min(contents.len());
+        let bounds: Vec<usize> = (0..=STREAMS).map(|idx| start_offset + (end_offset - start_offset) * idx / STREAMS).collect();
+        let mut streams: [Stream; STREAMS] = std::array::from_fn(|idx| {
+            Stream::new(contents, bounds[idx], bounds[idx + 1], idx != 0 || start_offset != 0)
+        });
+
+        // one byte of each stream per iteration: the streams do not depend on each
+        // other, so the CPU can overlap their work
+        while streams.iter().any(|stream| !stream.done) {
+            for (stream, table) in streams.iter_mut().zip(tables.iter_mut()) {
+                if !stream.done {
+                    stream.step(contents, table);
                 }
             }
         }
-        
-        for &byte in iter_1 {
-            byte_count_1+=1;
-
-            match byte {
-                b'\n' => {
-                    let temprature = temprature(&mut c_1);
-                    table_1.insert_or_update(&c_1.station, c_1.station_idx, c_1.hash, temprature);
-                    reset(&mut c_1);
-                    if byte_count_1 > byte_limit_1 {
-                        break;
-                    }
-                },
-                b';' => c_1.parsing_name = false,
-                _ if c_1.parsing_name => update_station(&mut c_1, byte),
-                b'-' => c_1.temp_multiplier = -1.0,
-                b'.' => c_1.parsing_int_part = false,
-                _ => update_temprature(&mut c_1, byte),
-            }     
-        }
-
-        for &byte in iter_2 {
-            byte_count_2+=1;
-
-            match byte {
-                b'\n' => {
-                    let temprature = temprature(&mut c_2);
-                    table_2.insert_or_update(&c_2.station, c_2.station_idx, c_2.hash, temprature);
-                    reset(&mut c_2);
-                    if byte_count_2 > byte_limit_2 {
-                        break;
-                    }
-                },
-                b';' => c_2.parsing_name = false,
-                _ if c_2.parsing_name => update_station(&mut c_2, byte),
-                b'-' => c_2.temp_multiplier = -1.0,
-                b'.' => c_2.parsing_int_part = false,
-                _ => update_temprature(&mut c_2, byte),
-            }     
-        }
     }
 
     let end_time = Instant::now();
     info!("Time taken to compute the stats: {} milliseconds", (end_time - start_time).as_millis());
-    table_1
+    tables
 }
 
-
-fn thread_run(thread_id: usize, filepath: &str, offset_counter: Arc<AtomicUsize>, file_size: usize) -> LPTable {
-    let mut file = File::open(filepath).unwrap();
-    compute(thread_id, filepath, offset_counter, file_size)
+fn distribute_work_chunks(path: &str, thread_count: usize, chunk_size: usize) -> Result<Vec<JoinHandle<Vec<LPTable>>>, Error> {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let file = File::open(path)?;
+    let contents = Arc::new(unsafe { Mmap::map(&file) }.map_err(Error::Mmap)?);
+    Ok((0..thread_count).map(|_| {
+        let (counter, contents) = (counter.clone(), contents.clone());
+        thread::spawn(move || compute(&contents, &counter, chunk_size))
+    }).collect())
 }
 
-pub fn distribute_work(path: &'static str, thread_count: usize) -> Vec<JoinHandle<LPTable>> {
-    let counter = Arc::new(AtomicUsize::new(0));
-    let mut handles = Vec::with_capacity(thread_count);
-    let file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
-    for thread_id in 0..thread_count {
-        let counter_clone = counter.clone();
-        handles.push(thread::spawn(move || {
-            thread_run(thread_id, path, counter_clone, file_size)
-        }));
-    }
-    handles
+/// Maps the file once and spawns `thread_count` workers that claim chunks of it
+/// from a shared counter. Each chunk is split into `STREAMS` sub-ranges.
+pub fn distribute_work(path: &str, thread_count: usize) -> Result<Vec<JoinHandle<Vec<LPTable>>>, Error> {
+    distribute_work_chunks(path, thread_count, CHUNK_SIZE)
 }
 
-pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, KeyedStat> {
-    let mut count = 0;
+/// Merges the tables of all the streams of all the workers.
+pub fn aggregate_result(handles: Vec<JoinHandle<Vec<LPTable>>>) -> Result<BTreeMap<String, KeyedStat>, Error> {
     let mut result: BTreeMap<String, KeyedStat> = BTreeMap::new();
-    for handle in handles {
-        let lptable = handle.join().unwrap();
+    let tables = handles.into_iter().map(|handle| handle.join().map_err(Error::worker_panic)).collect::<Result<Vec<_>, _>>()?;
+    for lptable in tables.into_iter().flatten() {
         for slot in lptable.occupied_slots {
             for ks in &lptable.table[slot] {
-                count += 1;
                 let key: &str;
                 unsafe {
                     key = str::from_utf8_unchecked(&ks.station[0..ks.len]);
@@ -273,7 +241,7 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
                 let maybe_stat = result.get_mut(key);
                 match maybe_stat {
                     None => {
-                        result.insert(String::from(key), ks.clone());
+                        result.insert(String::from(key), *ks);
                     },
                     Some(stat) => {
                         stat.sum += ks.sum;
@@ -286,32 +254,57 @@ pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, K
             }
         }
     }
-    result
+    Ok(result)
 }
 
 
-pub fn run(path: &'static str, thread_count: usize) -> BTreeMap<String, KeyedStat> {
-    let handles = distribute_work(path, thread_count);
-    let result = aggregate_result(handles);
-    print_result_btreemap_kstat(&result);
-    result
+pub fn run(path: &str, thread_count: usize) -> Result<BTreeMap<String, KeyedStat>, Error> {
+    let handles = distribute_work(path, thread_count)?;
+    aggregate_result(handles)
+}
+
+pub struct Attempt8;
+
+impl Solver for Attempt8 {
+    fn name(&self) -> &'static str {
+        "attempt8"
+    }
+
+    fn description(&self) -> &'static str {
+        "Threads claim 4MB chunks from a shared counter and parse their halves in one interleaved loop"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((run(input, config.thread_count)?, Rejected::default()))
+    }
 }
 
 
 #[test]
 fn test_impl() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
     }
-    env_logger::init();
-
-    let path = "data/test_small.csv";
-    let expected = attempt1::naive_btree_kstat(path);
-    let actual = run(path, 3);
-    for (station, ks) in expected.into_iter() {
-        let acs = actual.get(&station).unwrap();
-        assert_eq!(ks.max, acs.max);
-        assert_eq!(ks.min, acs.min);
-        assert_eq!(ks.sum/ks.count, acs.sum/acs.count);
+    let _ = env_logger::try_init();
+
+    crate::differential::assert_agrees(&Attempt8, "data/test_small.csv", 3);
+}
+
+#[test]
+fn test_streams() {
+    // chunks and sub-ranges that end on a newline, right after one, in the middle of
+    // a line or that hold no line start at all
+    for path in ["data/test_small.csv", "data/samples/measurements-1.txt", "data/samples/measurements-unicode.txt"] {
+        let expected = crate::attempt1::naive_btree_kstat(path);
+        for chunk_size in [1, 2, 7, 13, 14, 64, 1000, CHUNK_SIZE] {
+            for thread_count in [1, 3] {
+                let actual = aggregate_result(distribute_work_chunks(path, thread_count, chunk_size).unwrap()).unwrap();
+                assert_eq!(actual, expected, "{} in chunks of {} bytes with {} threads", path, chunk_size, thread_count);
+            }
+        }
     }
-}
\ No newline at end of file
+}
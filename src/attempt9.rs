@@ -1,15 +1,19 @@
-use core::num;
-use std::{arch::asm, collections::BTreeMap, env, fs::{self, File}, io::{Read, Seek, SeekFrom}, mem, ptr, thread::{self, current, JoinHandle}, time::Instant};
+use std::{arch::asm, collections::BTreeMap, fs::{self, File}, ptr, thread::{self, JoinHandle}, time::Instant};
 
-use crate::{attempt1, utils::{print_result_btreemap_kstat, print_result_hashmap, KeyedStat, Stat, MAX_LINE_SIZE, THREAD_COUNT}};
+use crate::solver::{Results, Solver, SolverConfig};
+use crate::arena::{Entry, NameArena};
+use crate::error::Error;
+use crate::utils::KeyedStat;
+use crate::validate::Rejected;
 use log::{debug, info};
 use std::str;
-use memmap2::{Mmap, MmapOptions};
+use memmap2::MmapOptions;
 
 
 struct LPTable {
     num_slots: usize,
-    table: Vec<Vec<KeyedStat>>,
+    table: Vec<Vec<Entry>>,
+    names: NameArena,
     size: usize,
     occupied_slots: Vec<usize>
 }
@@ -20,61 +24,30 @@ impl LPTable {
         LPTable {
             num_slots,
             table: vec![Vec::with_capacity(min_slot_size); num_slots],
+            names: NameArena::new(),
             size: 0,
             occupied_slots: Vec::with_capacity(num_slots)
         }
     }
 
-    fn get_collision_count(&self) -> usize {
-        let mut m = 0;
-        let mut count = 0;
-        for i in 0..self.num_slots {
-            if self.table[i].len() > 1 {
-                count += self.table[i].len();
-            }
-            m = std::cmp::max(self.table[i].len(), m);
-        }
-        count
-    }
-
-    fn len(&self) -> usize {
-        self.size
-    }
-
-
-    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: f32) {
+    fn insert_or_update(&mut self, station: &[u8; 100], len: usize, hash: usize, temp: i32) {
         let slot = hash & (self.num_slots-1);
         debug!("station={}, hash={}, temp={}", str::from_utf8(&station[0..len]).unwrap(), hash, temp);
 
-        for ks in &mut self.table[slot] {
-            // TODO: don't need to compare all the 100 bytes
-            if &ks.station != station {
-                assert_ne!(station[0..len], ks.station[0..ks.len]);
-                debug!("Collision for {} and {}", str::from_utf8(&station[0..len]).unwrap(), str::from_utf8(&ks.station[0..ks.len]).unwrap());
+        for entry in &mut self.table[slot] {
+            if !entry.matches(&self.names, hash, &station[0..len]) {
+                debug!("Collision for {} and {}", str::from_utf8(&station[0..len]).unwrap(), str::from_utf8(entry.name(&self.names)).unwrap());
                 continue;
             }
             // println!("Found multiple entries for {}", str::from_utf8(&station[0..len]).unwrap());
-            ks.sum += temp;
-            ks.count += 1.0;
-            ks.min = temp.min(ks.min);
-            ks.max = temp.max(ks.max);
+            entry.update(temp);
             return;
         }
-        let mut ks = KeyedStat{
-            min: temp,
-            max: temp,
-            count: 1.0,
-            len: len,
-            sum: temp,
-            station: [0;100],
-        };
-        unsafe {
-            ptr::copy_nonoverlapping(station.as_ptr(), ks.station.as_mut_ptr(), ks.station.len());
-        }
+        let offset = self.names.push(&station[0..len]);
         if self.table[slot].is_empty() {
             self.occupied_slots.push(slot);
         }
-        self.table[slot].push(ks);
+        self.table[slot].push(Entry::new(hash, offset, len as u32, temp));
         self.size+=1;
     }
 
@@ -88,27 +61,28 @@ struct Cursor {
     parsing_int_part: bool,
     station_idx: usize,
     hash: usize,
-    temp_multiplier: f32,
+    temp_multiplier: i32,
     station: [u8; 100],
 }
 
 impl Cursor {
     fn new() -> Cursor {
-        return Cursor {
+        Cursor {
             hash: 5381,
             station: [0; 100],
             station_idx: 0,
             parsing_name: true,
             temp_int_part: 0,
             temp_fraction_part: 0,
-            temp_multiplier: 1.0,
+            temp_multiplier: 1,
             parsing_int_part: true
-        };
+        }
     }
 }
 
-fn temprature(c: &mut Cursor) -> f32 {
-    c.temp_multiplier * (f32::try_from(c.temp_int_part).unwrap() + f32::from(c.temp_fraction_part) / 10.0)
+// temperature in tenths of a degree
+fn temprature(c: &mut Cursor) -> i32 {
+    c.temp_multiplier * (i32::from(c.temp_int_part) * 10 + i32::from(c.temp_fraction_part))
 }
     
 fn reset(c: &mut Cursor) {
@@ -118,7 +92,7 @@ fn reset(c: &mut Cursor) {
     c.parsing_name = true;
     c.temp_int_part = 0;
     c.temp_fraction_part = 0;
-    c.temp_multiplier = 1.0;
+    c.temp_multiplier = 1;
     c.parsing_int_part = true;
 }
 
@@ -127,7 +101,7 @@ fn update_temprature(c: &mut Cursor, byte: u8) {
         c.parsing_int_part = false;
         return;
     } else if byte == b'-' {
-        c.temp_multiplier = -1.0;
+        c.temp_multiplier = -1;
         return;
     }
 
@@ -159,7 +133,7 @@ fn find_newline(data: usize) -> i32 {
     if z == 0 {
         return -1;
     }
-    return (y.trailing_zeros() >> 3) as i32;
+    (z.trailing_zeros() >> 3) as i32
 }
 
 fn find_next_newline_vectorized(ptr: *const u8,  max_offset: isize) -> isize {
@@ -189,8 +163,8 @@ fn find_next_newline_vectorized(ptr: *const u8,  max_offset: isize) -> isize {
         }
     }
 
-    assert!(false, "unreacheable code");
-    0
+    // no newline up to `max_offset`, the offset past it
+    offset
 }
 
 fn find_next_newline(ptr: *const u8, max_offset: isize, c: &mut Cursor) -> isize {
@@ -208,8 +182,8 @@ fn find_next_newline(ptr: *const u8, max_offset: isize, c: &mut Cursor) -> isize
         }
     }
 
-    assert!(false, "unreacheable code");
-    0
+    // the last line of the file has no newline
+    offset
 }
 
 
@@ -220,7 +194,7 @@ fn find_semicolon(data: usize) -> i32 {
     if y == 0 {
         return -1;
     }
-    return (y.trailing_zeros() >> 3) as i32;
+    (y.trailing_zeros() >> 3) as i32
 }
 
 fn find_next_semicolon_vectorized(ptr: *const u8, max_offset: isize, hash: &mut usize, name: &mut [u8; 100]) -> isize {
@@ -236,9 +210,9 @@ fn find_next_semicolon_vectorized(ptr: *const u8, max_offset: isize, hash: &mut
                 bytes_remaining -= 8;
                 *hash = (*hash << 5) + *hash + data; 
             } else {
-                assert!(sc_idx >= 0 && sc_idx < 8);
+                assert!((0..8).contains(&sc_idx));
                 ptr::copy_nonoverlapping(&data as *const usize as *const u8, name.as_mut_ptr().offset(offset), sc_idx as usize);
-                data = !(0xFFFFFFFFFFFFFFFF << 8*sc_idx) & data;
+                data &= !(0xFFFFFFFFFFFFFFFF << (8*sc_idx));
                 *hash = (*hash << 5) + *hash + data; 
                 offset += sc_idx as isize;
                 return offset;
@@ -259,16 +233,16 @@ fn find_next_semicolon_vectorized(ptr: *const u8, max_offset: isize, hash: &mut
         }
     }
 
-    assert!(false, "unreacheable code");
-    0
+    // no semicolon up to `max_offset`, the offset past it
+    offset
 }
 
 
-fn compute(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: isize, file_size: usize) -> LPTable {
+fn compute(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_thread: isize, file_size: usize) -> Result<LPTable, Error> {
     let start_time = Instant::now();
 
-    let file = File::open(filepath).unwrap();
-    let contents = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file).unwrap() };
+    let file = File::open(filepath)?;
+    let contents = unsafe { MmapOptions::new().offset(start_offset.try_into().unwrap()).map(&file) }.map_err(Error::Mmap)?;
     let mut table = LPTable::new(130712, 4);
 
 
@@ -280,25 +254,24 @@ fn compute(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_
     let ignore_first_line = thread_id != 0;
     if ignore_first_line {  
         unsafe {    
-            let offset = find_next_newline_vectorized(buf.offset(buf_idx) as *const u8, (max_buf_idx-buf_idx));
-            buf_idx += offset as isize + 1;
+            let offset = find_next_newline_vectorized(buf.offset(buf_idx), max_buf_idx-buf_idx);
+            buf_idx += offset + 1;
         }
     }
 
     let mut c = Cursor::new();
-    while true {
+    loop {
         if buf_idx > file_size_per_thread || buf_idx > max_buf_idx {
             break;
         }
 
-        let mut station_name_len = 0;
-        unsafe {
-            station_name_len = find_next_semicolon_vectorized(buf.offset(buf_idx) as *const u8, (max_buf_idx-buf_idx), &mut c.hash, &mut c.station);
-        }
+        let station_name_len = unsafe {
+            find_next_semicolon_vectorized(buf.offset(buf_idx), max_buf_idx-buf_idx, &mut c.hash, &mut c.station)
+        };
         buf_idx +=  station_name_len + 1;
 
         unsafe {
-            buf_idx += find_next_newline(buf.offset(buf_idx), (max_buf_idx-buf_idx), &mut c);
+            buf_idx += find_next_newline(buf.offset(buf_idx), max_buf_idx-buf_idx, &mut c);
         }
         buf_idx +=1;
 
@@ -310,81 +283,90 @@ fn compute(thread_id: usize, filepath: &str, start_offset: usize, file_size_per_
 
     let end_time = Instant::now();
     info!("Time taken to compute the stats: {} milliseconds", (end_time - start_time).as_millis());
-    table
+    Ok(table)
 }
 
 
-pub fn distribute_work(path: &'static str, thread_count: usize) -> Vec<JoinHandle<LPTable>> {
+fn distribute_work(path: &str, thread_count: usize) -> Result<Vec<JoinHandle<Result<LPTable, Error>>>, Error> {
     let mut handles = Vec::with_capacity(thread_count);
-    let file_size: usize = fs::metadata(path).unwrap().len().try_into().unwrap();
+    let file_size: usize = fs::metadata(path)?.len().try_into().unwrap();
     let file_size_per_thread = file_size.div_ceil(thread_count);
-    for thread_id in 0..thread_count {
+    // the segments of a small file may run out before the threads do
+    for thread_id in (0..thread_count).take_while(|&thread_id| file_size_per_thread * thread_id < file_size) {
+        let path = path.to_string();
         handles.push(thread::spawn(move || {
-            compute(thread_id, path, file_size_per_thread * thread_id, file_size_per_thread as isize, file_size)
+            compute(thread_id, &path, file_size_per_thread * thread_id, file_size_per_thread as isize, file_size)
         }));
     }
-    handles
+    Ok(handles)
 }
 
-pub fn aggregate_result(handles: Vec<JoinHandle<LPTable>>) -> BTreeMap<String, KeyedStat> {
-    let mut count = 0;
+fn aggregate_result(handles: Vec<JoinHandle<Result<LPTable, Error>>>) -> Result<BTreeMap<String, KeyedStat>, Error> {
     let mut result: BTreeMap<String, KeyedStat> = BTreeMap::new();
     for handle in handles {
-        let lptable = handle.join().unwrap();
+        let lptable = handle.join().map_err(Error::worker_panic)??;
         for slot in lptable.occupied_slots {
-            for ks in &lptable.table[slot] {
-                count += 1;
+            for entry in &lptable.table[slot] {
                 let key: &str;
                 unsafe {
-                    key = str::from_utf8_unchecked(&ks.station[0..ks.len]);
+                    key = str::from_utf8_unchecked(entry.name(&lptable.names));
                 }
                 let maybe_stat = result.get_mut(key);
                 match maybe_stat {
                     None => {
-                        result.insert(String::from(key), ks.clone());
+                        result.insert(String::from(key), entry.to_keyed_stat(&lptable.names, 1));
                     },
                     Some(stat) => {
-                        stat.sum += ks.sum;
-                        stat.count += ks.count;
-                        stat.min = stat.min.min(ks.min);
-                        stat.max = stat.max.max(ks.max);
+                        stat.sum += entry.sum;
+                        stat.count += entry.count;
+                        stat.min = stat.min.min(entry.min);
+                        stat.max = stat.max.max(entry.max);
                     }
                 }
 
             }
         }
     }
-    result
+    Ok(result)
 }
 
 
-pub fn run(path: &'static str, thread_count: usize) -> BTreeMap<String, KeyedStat> {
-    let handles = distribute_work(path, thread_count);
-    let result = aggregate_result(handles);
-    print_result_btreemap_kstat(&result);
-    result
+pub fn run(path: &str, thread_count: usize) -> Result<BTreeMap<String, KeyedStat>, Error> {
+    let handles = distribute_work(path, thread_count)?;
+    aggregate_result(handles)
 }
 
+pub struct Attempt9;
 
-#[test]
-fn test_impl() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
+impl Solver for Attempt9 {
+    fn name(&self) -> &'static str {
+        "attempt9"
     }
-    env_logger::init();
-
-    let path = "data/test_small.csv";
-    let expected = attempt1::naive_btree_kstat(path);
-    let actual = run(path, 1);
-    for (station, ks) in expected.into_iter() {
-        let acs = actual.get(&station).unwrap();
-        assert_eq!(ks.max, acs.max);
-        assert_eq!(ks.min, acs.min);
-        assert_eq!(ks.sum/ks.count, acs.sum/acs.count);
+
+    fn description(&self) -> &'static str {
+        "Word at a time (SWAR) delimiter scanning"
+    }
+
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results {
+        run(input, config.thread_count).unwrap_or_else(|e| panic!("{}: {}", input, e))
+    }
+
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+        Ok((run(input, config.thread_count)?, Rejected::default()))
     }
 }
 
 
+#[test]
+fn test_impl() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
+    }
+    let _ = env_logger::try_init();
+
+    crate::differential::assert_agrees(&Attempt9, "data/test_small.csv", 1);
+}
+
 
 #[test]
 fn test_find_next_semicolon() {
@@ -426,4 +408,39 @@ fn test_find_next_newline() {
 
     let bytes = "000000001111111122\n";
     assert_eq!(find_next_newline_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize), 18);
+
+    // the end of the input
+    let bytes = "00000000111";
+    assert_eq!(find_next_newline_vectorized(bytes.as_ptr(), (bytes.len()-1) as isize), 11);
+    let mut c = Cursor::new();
+    assert_eq!(find_next_newline(bytes.as_ptr(), (bytes.len()-1) as isize, &mut c), 11);
+}
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+#[cfg(test)]
+proptest! {
+    // the name buffer holds 100 bytes, so the semicolon is at most 100 bytes in
+    #[test]
+    fn prop_find_next_semicolon_vectorized(name in proptest::collection::vec(any::<u8>().prop_filter("not a semicolon", |&b| b != b';'), 0..=100),
+                                           rest in proptest::collection::vec(any::<u8>(), 0..20)) {
+        let mut contents = name.clone();
+        contents.push(b';');
+        contents.extend_from_slice(&rest);
+        let mut hash = 0;
+        let mut station = [0; 100];
+        let offset = find_next_semicolon_vectorized(contents.as_ptr(), contents.len() as isize - 1, &mut hash, &mut station);
+        prop_assert_eq!(offset, name.len() as isize);
+        prop_assert_eq!(&station[..name.len()], &name[..]);
+    }
+
+    #[test]
+    fn prop_find_next_newline_vectorized(line in proptest::collection::vec(any::<u8>().prop_filter("not a newline", |&b| b != b'\n'), 0..200),
+                                         rest in proptest::collection::vec(any::<u8>(), 0..20)) {
+        let mut contents = line.clone();
+        contents.push(b'\n');
+        contents.extend_from_slice(&rest);
+        prop_assert_eq!(find_next_newline_vectorized(contents.as_ptr(), contents.len() as isize - 1), line.len() as isize);
+    }
 }
@@ -0,0 +1,230 @@
+use std::{env, fmt, fs::File, io::{self, BufWriter}, process, thread, time::Instant};
+
+use onebrc::generate::{self, GeneratorConfig, NameLength};
+use onebrc::utils::THREAD_COUNT;
+use onebrc::validate::MAX_NAME_LEN;
+
+const DEFAULT_OUTPUT: &str = "data/measurements.txt";
+const STDOUT_OUTPUT: &str = "-";
+
+const USAGE: &str = "\
+usage: generate [OPTIONS] ROWS
+
+Writes ROWS measurements (e.g. 1000000, 1M or 1B) of stations drawn from a station list,
+with temperatures from a normal distribution around the mean of each station. The same
+options and seed give the same file whatever the number of threads.
+
+options:
+  -o, --output <FILE>        file to write, - for stdout (default: data/measurements.txt)
+  -s, --seed <N>             seed of the random numbers (default: 0)
+      --stations <FILE>      station list, name;mean[;stddev] per line (default: the 413
+                             stations of the original challenge, built in)
+      --station-count <N>    number of stations drawn from the list (default: 413)
+      --stddev <DEGREES>     standard deviation of the stations the list gives none for
+                             (default: 10)
+      --name-length <LEN>    station name length in bytes: original, N, or MIN-MAX for a
+                             uniform length between both (default: original)
+      --unicode <FRACTION>   fraction of the station names given non-ASCII characters,
+                             0 to 1 (default: 0)
+  -t, --threads <N>          number of generator threads (default: available parallelism)
+  -h, --help                 print this message";
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    output: String,
+    stations: Option<String>,
+    stddev: f64,
+    config: GeneratorConfig,
+}
+
+#[derive(Debug, PartialEq)]
+enum CliError {
+    Help,
+    MissingRows,
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownOption(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::MissingRows => write!(f, "missing the number of rows"),
+            CliError::MissingValue(opt) => write!(f, "missing value for option '{}'", opt),
+            CliError::InvalidValue(opt, value) => write!(f, "invalid value '{}' for option '{}'", value, opt),
+            CliError::UnknownOption(opt) => write!(f, "unknown option '{}'", opt),
+        }
+    }
+}
+
+// "1000", "10k", "1M" or "1B"
+fn parse_rows(value: &str) -> Option<u64> {
+    let (digits, scale) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 1_000),
+        b'm' | b'M' => (&value[..value.len() - 1], 1_000_000),
+        b'b' | b'B' => (&value[..value.len() - 1], 1_000_000_000),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+fn parse_name_length(value: &str) -> Option<NameLength> {
+    let valid = |len: usize| (1..=MAX_NAME_LEN).contains(&len);
+    match value.split_once('-') {
+        _ if value == "original" => Some(NameLength::Original),
+        Some((min, max)) => {
+            let (min, max) = (min.parse().ok()?, max.parse().ok()?);
+            (valid(min) && valid(max) && min <= max).then_some(NameLength::Uniform(min, max))
+        },
+        None => value.parse().ok().filter(|&len| valid(len)).map(NameLength::Fixed),
+    }
+}
+
+fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, CliError> {
+    let mut parsed = Args {
+        output: String::from(DEFAULT_OUTPUT),
+        stations: None,
+        stddev: generate::DEFAULT_STDDEV,
+        config: GeneratorConfig::new(0),
+    };
+    parsed.config.thread_count = thread::available_parallelism().map(|n| n.get()).unwrap_or(THREAD_COUNT);
+    let mut rows = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        // accept both "--opt value" and "--opt=value"
+        let (opt, inline_value) = match arg.split_once('=') {
+            Some((opt, value)) if arg.starts_with("--") => (opt.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || inline_value.clone().or_else(|| iter.next()).ok_or(CliError::MissingValue(opt.clone()));
+        let invalid = |value: String| CliError::InvalidValue(opt.clone(), value);
+
+        match opt.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-o" | "--output" => parsed.output = value()?,
+            "--stations" => parsed.stations = Some(value()?),
+            "-s" | "--seed" => {
+                let value = value()?;
+                parsed.config.seed = value.parse().map_err(|_| invalid(value))?;
+            },
+            "--station-count" => {
+                let value = value()?;
+                parsed.config.station_count = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| invalid(value))?;
+            },
+            "--stddev" => {
+                let value = value()?;
+                parsed.stddev = value.parse().ok().filter(|&stddev: &f64| stddev >= 0.0).ok_or_else(|| invalid(value))?;
+            },
+            "--name-length" => {
+                let value = value()?;
+                parsed.config.name_length = parse_name_length(&value).ok_or_else(|| invalid(value))?;
+            },
+            "--unicode" => {
+                let value = value()?;
+                parsed.config.unicode = value.parse().ok().filter(|fraction| (0.0..=1.0).contains(fraction)).ok_or_else(|| invalid(value))?;
+            },
+            "-t" | "--threads" => {
+                let value = value()?;
+                parsed.config.thread_count = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| invalid(value))?;
+            },
+            _ if opt.starts_with('-') => return Err(CliError::UnknownOption(opt)),
+            _ if rows.is_none() => rows = Some(parse_rows(&arg).ok_or_else(|| CliError::InvalidValue(String::from("ROWS"), arg))?),
+            _ => return Err(CliError::InvalidValue(String::from("ROWS"), arg)),
+        }
+    }
+    parsed.config.rows = rows.ok_or(CliError::MissingRows)?;
+    Ok(parsed)
+}
+
+fn main() {
+    let args = match parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(CliError::Help) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let list = match args.stations.as_deref().map_or_else(|| Ok(generate::default_stations(args.stddev)), |path| generate::read_stations(path, args.stddev)) {
+        Ok(list) if !list.is_empty() => list,
+        Ok(_) => {
+            eprintln!("error: no stations in '{}'", args.stations.unwrap_or_default());
+            process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("error: cannot read the stations: {}", e);
+            process::exit(1);
+        }
+    };
+    let stations = generate::choose_stations(&list, &args.config);
+
+    let start_time = Instant::now();
+    let written = if args.output == STDOUT_OUTPUT {
+        generate::write_rows(&stations, &args.config, BufWriter::new(io::stdout().lock()))
+    } else {
+        File::create(&args.output).and_then(|file| generate::write_rows(&stations, &args.config, file))
+    };
+    if let Err(e) = written {
+        eprintln!("error: cannot write '{}': {}", args.output, e);
+        process::exit(1);
+    }
+    if args.output != STDOUT_OUTPUT {
+        eprintln!("wrote {} rows of {} stations to {} in {} ms", args.config.rows, stations.len(), args.output, start_time.elapsed().as_millis());
+    }
+}
+
+
+#[cfg(test)]
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_parse() {
+    let parsed = parse(args(&["1M"])).unwrap();
+    assert_eq!(parsed.output, DEFAULT_OUTPUT);
+    assert_eq!(parsed.stations, None);
+    assert_eq!(parsed.config.rows, 1_000_000);
+    assert_eq!(parsed.config.seed, 0);
+    assert_eq!(parsed.config.station_count, generate::DEFAULT_STATION_COUNT);
+    assert_eq!(parsed.config.name_length, NameLength::Original);
+
+    let parsed = parse(args(&["-o", "-", "--seed=7", "250", "--name-length", "3-20", "--unicode", "0.5", "-t", "3", "--station-count", "10000"])).unwrap();
+    assert_eq!(parsed.output, STDOUT_OUTPUT);
+    assert_eq!(parsed.config.rows, 250);
+    assert_eq!(parsed.config.seed, 7);
+    assert_eq!(parsed.config.name_length, NameLength::Uniform(3, 20));
+    assert_eq!(parsed.config.unicode, 0.5);
+    assert_eq!(parsed.config.thread_count, 3);
+    assert_eq!(parsed.config.station_count, 10_000);
+    assert_eq!(parse(args(&["1", "--name-length", "100"])).unwrap().config.name_length, NameLength::Fixed(100));
+}
+
+#[test]
+fn test_parse_rows() {
+    assert_eq!(parse_rows("1000"), Some(1000));
+    assert_eq!(parse_rows("10k"), Some(10_000));
+    assert_eq!(parse_rows("100M"), Some(100_000_000));
+    assert_eq!(parse_rows("1B"), Some(1_000_000_000));
+    assert_eq!(parse_rows("M"), None);
+    assert_eq!(parse_rows("1.5M"), None);
+    assert_eq!(parse_rows("99999999999B"), None);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(parse(args(&[])), Err(CliError::MissingRows));
+    assert_eq!(parse(args(&["1", "2"])), Err(CliError::InvalidValue(String::from("ROWS"), String::from("2"))));
+    assert_eq!(parse(args(&["lots"])), Err(CliError::InvalidValue(String::from("ROWS"), String::from("lots"))));
+    assert_eq!(parse(args(&["1", "--seed"])), Err(CliError::MissingValue(String::from("--seed"))));
+    assert_eq!(parse(args(&["1", "--name-length", "0"])), Err(CliError::InvalidValue(String::from("--name-length"), String::from("0"))));
+    assert_eq!(parse(args(&["1", "--name-length", "20-3"])), Err(CliError::InvalidValue(String::from("--name-length"), String::from("20-3"))));
+    assert_eq!(parse(args(&["1", "--unicode", "2"])), Err(CliError::InvalidValue(String::from("--unicode"), String::from("2"))));
+    assert_eq!(parse(args(&["1", "--bogus"])), Err(CliError::UnknownOption(String::from("--bogus"))));
+    assert_eq!(parse(args(&["-h"])), Err(CliError::Help));
+}
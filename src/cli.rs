@@ -0,0 +1,249 @@
+use std::{fmt, thread};
+
+use onebrc::utils::THREAD_COUNT;
+use onebrc::validate::MAX_PRECISION;
+use onebrc::{Error, ErrorPolicy};
+
+pub const DEFAULT_INPUT: &str = "data/measurements.txt";
+pub const DEFAULT_IMPL: &str = "attempt10";
+pub const DEFAULT_FORMAT: &str = "text";
+pub const STDIN_INPUT: &str = "-";
+
+pub const USAGE: &str = "\
+usage: attempt10 [OPTIONS] [INPUT]...
+
+Computes min/mean/max per weather station over all the INPUTs (default: data/measurements.txt).
+An INPUT may be a glob pattern such as 'data/*.txt', quoted so that the shell leaves it.
+An INPUT of - reads stdin, which is streamed through attempt10 whatever --impl says.
+Compressed inputs (gzip, zstd, lz4) are detected and streamed the same way, which needs a
+build with the `compression` feature.
+
+options:
+  -i, --impl <NAME>      implementation to run, attempt1..attempt10 (default: attempt10)
+  -t, --threads <N>      number of worker threads (default: available parallelism)
+  -o, --output <FILE>    write the results to FILE instead of stdout, FILE is left
+                         untouched if the run fails
+  -f, --format <FORMAT>  output format: text, json, csv, ndjson or arrow (default: text),
+                         arrow needs a build with the `arrow` feature
+      --precision <N>    fractional digits of the temperatures (attempt10 only), 1 to 6,
+                         or auto to detect them from the start of each input (default: auto)
+      --on-error <POLICY>  what to do with malformed rows (attempt10 only): strict stops
+                         at the first one, skip ignores them, quarantine also writes the
+                         first 64 MiB of them to INPUT.rejected (default: strict)
+  -l, --list             list the available implementations
+      --per-file         also write the results of each INPUT to INPUT.<format extension>
+      --validate         check each INPUT for malformed rows instead of aggregating it
+      --bench-hashes     aggregate each INPUT with attempt10 and each of the station name
+                         hashes, reporting their collisions and throughput
+  -h, --help             print this message
+
+exit status:
+  0  success
+  1  any other failure, e.g. --validate found malformed rows
+  2  invalid arguments
+  3  an INPUT cannot be read
+  4  an INPUT cannot be memory mapped
+  5  an INPUT has a malformed row, under --on-error strict
+  6  a worker thread panicked
+  7  an INPUT has more distinct stations than the hash table holds";
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub inputs: Vec<String>,
+    pub implementation: String,
+    pub threads: usize,
+    pub output: Option<String>,
+    pub format: String,
+    pub policy: ErrorPolicy,
+    pub precision: Option<u32>,
+    pub list: bool,
+    pub validate: bool,
+    pub per_file: bool,
+    pub bench_hashes: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    Help,
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownOption(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::MissingValue(opt) => write!(f, "missing value for option '{}'", opt),
+            CliError::InvalidValue(opt, value) => write!(f, "invalid value '{}' for option '{}'", value, opt),
+            CliError::UnknownOption(opt) => write!(f, "unknown option '{}'", opt),
+        }
+    }
+}
+
+/// Exit status of a failed aggregation, as listed in USAGE.
+pub fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::Io(_) => 3,
+        Error::Mmap(_) => 4,
+        Error::Parse(_) => 5,
+        Error::WorkerPanic(_) => 6,
+        Error::CapacityExceeded(_) => 7,
+        Error::InvalidPrecision(_) | Error::NoThreads => 2,
+    }
+}
+
+pub fn default_thread_count() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(THREAD_COUNT)
+}
+
+fn parse_threads(opt: &str, value: String) -> Result<usize, CliError> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(CliError::InvalidValue(opt.to_string(), value)),
+    }
+}
+
+fn parse_precision(opt: &str, value: String) -> Result<Option<u32>, CliError> {
+    if value == "auto" {
+        return Ok(None);
+    }
+    match value.parse::<u32>() {
+        Ok(n) if (1..=MAX_PRECISION).contains(&n) => Ok(Some(n)),
+        _ => Err(CliError::InvalidValue(opt.to_string(), value)),
+    }
+}
+
+fn parse_policy(opt: &str, value: String) -> Result<ErrorPolicy, CliError> {
+    match value.as_str() {
+        "strict" => Ok(ErrorPolicy::Strict),
+        "skip" => Ok(ErrorPolicy::Skip),
+        "quarantine" => Ok(ErrorPolicy::Quarantine),
+        _ => Err(CliError::InvalidValue(opt.to_string(), value)),
+    }
+}
+
+/// Parses the command line arguments, excluding the program name.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, CliError> {
+    let mut parsed = Args {
+        inputs: Vec::new(),
+        implementation: String::from(DEFAULT_IMPL),
+        threads: default_thread_count(),
+        output: None,
+        format: String::from(DEFAULT_FORMAT),
+        policy: ErrorPolicy::Strict,
+        precision: None,
+        list: false,
+        validate: false,
+        per_file: false,
+        bench_hashes: false,
+    };
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        // accept both "--opt value" and "--opt=value"
+        let (opt, inline_value) = match arg.split_once('=') {
+            Some((opt, value)) if arg.starts_with("--") => (opt.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || inline_value.clone().or_else(|| iter.next()).ok_or(CliError::MissingValue(opt.clone()));
+
+        match opt.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-l" | "--list" => parsed.list = true,
+            "--validate" => parsed.validate = true,
+            "--per-file" => parsed.per_file = true,
+            "--bench-hashes" => parsed.bench_hashes = true,
+            "-i" | "--impl" => parsed.implementation = value()?,
+            "-t" | "--threads" => parsed.threads = parse_threads(&opt, value()?)?,
+            "-o" | "--output" => parsed.output = Some(value()?),
+            "-f" | "--format" => parsed.format = value()?,
+            "--precision" => parsed.precision = parse_precision(&opt, value()?)?,
+            "--on-error" => parsed.policy = parse_policy(&opt, value()?)?,
+            "--" => {
+                parsed.inputs.extend(iter.by_ref());
+                break;
+            },
+            _ if opt.starts_with('-') && opt != STDIN_INPUT => return Err(CliError::UnknownOption(opt)),
+            _ => parsed.inputs.push(arg),
+        }
+    }
+
+    if parsed.inputs.is_empty() {
+        parsed.inputs.push(String::from(DEFAULT_INPUT));
+    }
+    Ok(parsed)
+}
+
+
+#[cfg(test)]
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_parse_defaults() {
+    let parsed = parse(args(&[])).unwrap();
+    assert_eq!(parsed.inputs, vec![DEFAULT_INPUT]);
+    assert_eq!(parsed.implementation, DEFAULT_IMPL);
+    assert_eq!(parsed.threads, default_thread_count());
+    assert_eq!(parsed.output, None);
+    assert_eq!(parsed.format, DEFAULT_FORMAT);
+    assert_eq!(parsed.policy, ErrorPolicy::Strict);
+    assert_eq!(parsed.precision, None);
+    assert!(!parsed.list);
+    assert!(!parsed.validate);
+    assert!(!parsed.per_file);
+    assert!(!parsed.bench_hashes);
+}
+
+#[test]
+fn test_parse_options() {
+    let parsed = parse(args(&["-t", "4", "--impl=attempt7", "a.txt", "--output", "out.txt", "b.txt", "-f", "csv"])).unwrap();
+    assert_eq!(parsed.inputs, vec!["a.txt", "b.txt"]);
+    assert_eq!(parsed.implementation, "attempt7");
+    assert_eq!(parsed.threads, 4);
+    assert_eq!(parsed.output, Some(String::from("out.txt")));
+    assert_eq!(parsed.format, "csv");
+
+    let parsed = parse(args(&["--", "-weird-name.txt"])).unwrap();
+    assert_eq!(parsed.inputs, vec!["-weird-name.txt"]);
+
+    let parsed = parse(args(&["a.txt", "-"])).unwrap();
+    assert_eq!(parsed.inputs, vec!["a.txt", STDIN_INPUT]);
+
+    assert!(parse(args(&["--list"])).unwrap().list);
+    assert!(parse(args(&["--validate", "a.txt"])).unwrap().validate);
+    assert!(parse(args(&["--per-file", "a.txt", "b.txt"])).unwrap().per_file);
+    assert!(parse(args(&["--bench-hashes", "a.txt"])).unwrap().bench_hashes);
+    assert_eq!(parse(args(&["--on-error", "skip"])).unwrap().policy, ErrorPolicy::Skip);
+    assert_eq!(parse(args(&["--on-error=quarantine"])).unwrap().policy, ErrorPolicy::Quarantine);
+    assert_eq!(parse(args(&["--precision", "4"])).unwrap().precision, Some(4));
+    assert_eq!(parse(args(&["--precision=4", "--precision=auto"])).unwrap().precision, None);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(parse(args(&["--threads"])), Err(CliError::MissingValue(String::from("--threads"))));
+    assert_eq!(parse(args(&["--threads", "0"])), Err(CliError::InvalidValue(String::from("--threads"), String::from("0"))));
+    assert_eq!(parse(args(&["--on-error", "ignore"])), Err(CliError::InvalidValue(String::from("--on-error"), String::from("ignore"))));
+    assert_eq!(parse(args(&["--precision", "7"])), Err(CliError::InvalidValue(String::from("--precision"), String::from("7"))));
+    assert_eq!(parse(args(&["--bogus"])), Err(CliError::UnknownOption(String::from("--bogus"))));
+    assert_eq!(parse(args(&["-h"])), Err(CliError::Help));
+}
+
+#[test]
+fn test_exit_code() {
+    use onebrc::validate::{RowError, RowErrorKind};
+    let row = |kind| RowError { line: 1, offset: 0, kind };
+    let errors = [
+        Error::Io(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        Error::Mmap(std::io::Error::from(std::io::ErrorKind::InvalidInput)),
+        Error::from(row(RowErrorKind::EmptyLine)),
+        Error::WorkerPanic(String::from("boom")),
+        Error::from(row(RowErrorKind::TooManyStations(10))),
+        Error::InvalidPrecision(0),
+        Error::NoThreads,
+    ];
+    assert_eq!(errors.iter().map(exit_code).collect::<Vec<_>>(), [3, 4, 5, 6, 7, 2, 2]);
+}
@@ -0,0 +1,311 @@
+use std::{fs::File, io::{self, Read}};
+
+/// Compression formats recognized in the input files, available with the
+/// `compression` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    /// Recognizes the format from the magic bytes at the start of a file.
+    pub fn detect(header: &[u8]) -> Option<Compression> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if header.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Some(Compression::Lz4)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+}
+
+/// Returns the compression of the file at `path`, `None` for plain text.
+pub fn detect_file(path: &str) -> io::Result<Option<Compression>> {
+    let mut header = Vec::with_capacity(4);
+    File::open(path)?.take(4).read_to_end(&mut header)?;
+    Ok(Compression::detect(&header))
+}
+
+/// Opens a compressed file for streaming, `None` if the file is plain text and
+/// should be mapped instead. Files made of independent frames, i.e. zstd files with
+/// several frames (seekable zstd) or BGZF gzip files, are mapped and decompressed by
+/// `thread_count` threads, other files are read by a single decoder.
+pub fn open(path: &str, thread_count: usize) -> io::Result<Option<Box<dyn Read + Send>>> {
+    match detect_file(path)? {
+        Some(compression) => decoder(compression, File::open(path)?, thread_count).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(feature = "compression")]
+fn decoder(compression: Compression, file: File, thread_count: usize) -> io::Result<Box<dyn Read + Send>> {
+    use std::{io::{BufReader, Seek}, sync::Arc};
+    use memmap2::Mmap;
+
+    // the file is only mapped to find its frames, and kept mapped if there are several
+    Ok(match compression {
+        Compression::Gzip => {
+            let mut header = Vec::with_capacity(18);
+            (&file).take(18).read_to_end(&mut header)?;
+            (&file).rewind()?;
+            let mmap = if frames::is_bgzf(&header) { Some(unsafe { Mmap::map(&file)? }) } else { None };
+            let members = mmap.as_deref().and_then(frames::bgzf_members);
+            match (mmap, members) {
+                (Some(mmap), Some(members)) => Box::new(frames::FrameReader::new(Arc::new(mmap), members, thread_count, frames::decode_gzip)),
+                _ => Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+            }
+        },
+        Compression::Zstd => {
+            let mmap = unsafe { Mmap::map(&file)? };
+            match frames::zstd_frames(&mmap)? {
+                Some(frames) => Box::new(frames::FrameReader::new(Arc::new(mmap), frames, thread_count, frames::decode_zstd)),
+                None => Box::new(zstd::stream::read::Decoder::new(file)?),
+            }
+        },
+        Compression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(BufReader::new(file))),
+    })
+}
+
+#[cfg(not(feature = "compression"))]
+fn decoder(compression: Compression, _file: File, _thread_count: usize) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} input needs the `compression` feature", compression.name())))
+}
+
+
+#[cfg(feature = "compression")]
+mod frames {
+    use std::{io::{self, Read}, ops::Range, sync::{mpsc, Arc}, thread};
+    use memmap2::Mmap;
+
+    // frames are handed over in pieces, so that a large frame is not held in memory
+    const PIECE_SIZE: usize = 1024 * 1024;
+
+    // `None` ends a frame
+    type Piece = io::Result<Option<Vec<u8>>>;
+
+    type Decode = fn(&[u8]) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Decompresses independent frames of a mapped file on several threads, thread k
+    /// takes frames k, k + thread_count, ... and the frames are read back in order.
+    pub struct FrameReader {
+        receivers: Vec<mpsc::Receiver<Piece>>,
+        frame_count: usize,
+        next: usize,
+        current: Vec<u8>,
+        pos: usize,
+    }
+
+    impl FrameReader {
+        pub fn new(mmap: Arc<Mmap>, frames: Vec<Range<usize>>, thread_count: usize, decode: Decode) -> FrameReader {
+            let frame_count = frames.len();
+            let frames = Arc::new(frames);
+            let thread_count = thread_count.clamp(1, frame_count.max(1));
+            let receivers = (0..thread_count).map(|k| {
+                // one piece ahead per thread bounds the memory use
+                let (sender, receiver) = mpsc::sync_channel(1);
+                let (mmap, frames) = (mmap.clone(), frames.clone());
+                thread::spawn(move || {
+                    for frame in frames.iter().skip(k).step_by(thread_count) {
+                        // fails once the reader is dropped
+                        if send_frame(&sender, decode(&mmap[frame.clone()])).is_err() {
+                            break;
+                        }
+                    }
+                });
+                receiver
+            }).collect();
+            FrameReader { receivers, frame_count, next: 0, current: Vec::new(), pos: 0 }
+        }
+    }
+
+    impl Read for FrameReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.pos == self.current.len() {
+                if self.next == self.frame_count {
+                    return Ok(0);
+                }
+                let receiver = &self.receivers[self.next % self.receivers.len()];
+                match receiver.recv().map_err(|_| io::Error::other("a decompression thread panicked"))?? {
+                    Some(piece) => {
+                        self.current = piece;
+                        self.pos = 0;
+                    },
+                    None => self.next += 1,
+                }
+            }
+            let len = buf.len().min(self.current.len() - self.pos);
+            buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    fn send_frame(sender: &mpsc::SyncSender<Piece>, decoder: io::Result<Box<dyn Read + '_>>) -> Result<(), mpsc::SendError<Piece>> {
+        let mut decoder = match decoder {
+            Ok(decoder) => decoder,
+            Err(e) => return sender.send(Err(e)),
+        };
+        loop {
+            let mut piece = Vec::with_capacity(PIECE_SIZE);
+            match decoder.by_ref().take(PIECE_SIZE as u64).read_to_end(&mut piece) {
+                Ok(0) => return sender.send(Ok(None)),
+                Ok(_) => sender.send(Ok(Some(piece)))?,
+                Err(e) => return sender.send(Err(e)),
+            }
+        }
+    }
+
+    pub fn decode_gzip(member: &[u8]) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(flate2::read::GzDecoder::new(member)))
+    }
+
+    pub fn decode_zstd(frame: &[u8]) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(frame)?))
+    }
+
+    /// Whether `header` starts with a BGZF member, i.e. a gzip header with a BC extra
+    /// field.
+    pub fn is_bgzf(header: &[u8]) -> bool {
+        header.len() >= 18 && header[..4] == [0x1f, 0x8b, 8, 4] && header[12..16] == [b'B', b'C', 2, 0]
+    }
+
+    /// Splits a BGZF file (e.g. from bgzip) into its gzip members, using the member
+    /// size stored in the BC extra field. `None` for other gzip files and for a single
+    /// member.
+    pub fn bgzf_members(data: &[u8]) -> Option<Vec<Range<usize>>> {
+        let mut members = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let header = data.get(offset..offset + 18)?;
+            if !is_bgzf(header) {
+                return None;
+            }
+            let size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+            members.push(offset..offset + size);
+            offset += size;
+        }
+        (offset == data.len() && members.len() > 1).then_some(members)
+    }
+
+    /// Splits a zstd file into its frames, `None` if there is a single frame.
+    pub fn zstd_frames(data: &[u8]) -> io::Result<Option<Vec<Range<usize>>>> {
+        use zstd::zstd_safe;
+
+        let mut frames = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let frame = &data[offset..];
+            let size = zstd_safe::find_frame_compressed_size(frame)
+                .map_err(|code| io::Error::new(io::ErrorKind::InvalidData, zstd_safe::get_error_name(code)))?;
+            frames.push(offset..offset + size);
+            offset += size;
+        }
+        Ok((frames.len() > 1).then_some(frames))
+    }
+}
+
+
+#[cfg(all(test, feature = "compression"))]
+fn compressed_files(contents: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+    use std::io::Write;
+
+    let gzip = |part: &[u8], extra: Option<Vec<u8>>| {
+        let builder = match extra {
+            Some(extra) => flate2::GzBuilder::new().extra(extra),
+            None => flate2::GzBuilder::new(),
+        };
+        let mut encoder = builder.write(Vec::new(), flate2::Compression::default());
+        encoder.write_all(part).unwrap();
+        encoder.finish().unwrap()
+    };
+    let bgzf_member = |part: &[u8]| {
+        let mut member = gzip(part, Some(vec![b'B', b'C', 2, 0, 0, 0]));
+        let size = (member.len() - 1) as u16;
+        member[16..18].copy_from_slice(&size.to_le_bytes());
+        member
+    };
+    let parts: Vec<&[u8]> = contents.chunks(100).collect();
+
+    let mut lz4 = lz4_flex::frame::FrameEncoder::new(Vec::new());
+    lz4.write_all(contents).unwrap();
+    vec![
+        ("gzip", gzip(contents, None)),
+        ("multi-member gzip", parts.iter().flat_map(|part| gzip(part, None)).collect()),
+        ("bgzf", parts.iter().chain([&&b""[..]]).flat_map(|part| bgzf_member(part)).collect()),
+        ("zstd", zstd::encode_all(contents, 3).unwrap()),
+        ("multi-frame zstd", parts.iter().flat_map(|part| zstd::encode_all(*part, 3).unwrap()).collect()),
+        ("lz4", lz4.finish().unwrap()),
+    ]
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Some(Compression::Gzip));
+    assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0]), Some(Compression::Zstd));
+    assert_eq!(Compression::detect(&[0x04, 0x22, 0x4d, 0x18]), Some(Compression::Lz4));
+    assert_eq!(Compression::detect(b"Hamburg;12.0\n"), None);
+    assert_eq!(Compression::detect(&[0x1f]), None);
+    assert_eq!(detect_file("data/test_small.csv").unwrap(), None);
+    assert!(open("data/test_small.csv", 4).unwrap().is_none());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_open() {
+    let contents = std::fs::read("data/test_small.csv").unwrap();
+    let path = std::env::temp_dir().join(format!("onebrc-compression-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let expected = crate::aggregate_file("data/test_small.csv", 1).unwrap();
+    for (name, compressed) in compressed_files(&contents) {
+        std::fs::write(path, &compressed).unwrap();
+        assert_eq!(crate::aggregate_reader(open(path, 2).unwrap().unwrap(), 2).unwrap(), expected, "{}", name);
+        for thread_count in [1, 3] {
+            let mut decompressed = Vec::new();
+            open(path, thread_count).unwrap().expect(name).read_to_end(&mut decompressed).unwrap();
+            assert!(decompressed == contents, "{} with {} threads", name, thread_count);
+        }
+    }
+
+    let mut truncated = zstd::encode_all(&contents[..100], 3).unwrap();
+    truncated.extend(&zstd::encode_all(&contents[100..], 3).unwrap()[..20]);
+    std::fs::write(path, &truncated).unwrap();
+    assert_eq!(open(path, 2).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(not(feature = "compression"))]
+#[test]
+fn test_open_unsupported() {
+    let path = std::env::temp_dir().join(format!("onebrc-compression-{}", std::process::id()));
+    std::fs::write(&path, [0x28, 0xb5, 0x2f, 0xfd, 0]).unwrap();
+    let error = open(path.to_str().unwrap(), 2).err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    assert_eq!(error.to_string(), "zstd input needs the `compression` feature");
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_frames() {
+    let contents = std::fs::read("data/test_small.csv").unwrap();
+    let files = compressed_files(&contents);
+    assert!(!frames::is_bgzf(&files[0].1) && frames::is_bgzf(&files[2].1));
+    assert!(frames::bgzf_members(&files[0].1).is_none());
+    assert!(frames::bgzf_members(&files[1].1).is_none());
+    assert_eq!(frames::bgzf_members(&files[2].1).unwrap().len(), contents.len().div_ceil(100) + 1);
+    assert!(frames::zstd_frames(&files[3].1).unwrap().is_none());
+    assert_eq!(frames::zstd_frames(&files[4].1).unwrap().unwrap().len(), contents.len().div_ceil(100));
+}
@@ -0,0 +1,317 @@
+use std::{fmt, fs, io, panic::{self, AssertUnwindSafe}, path::Path};
+
+use crate::attempt1;
+use crate::generate::{self, GeneratorConfig, NameLength, StationSpec};
+use crate::solver::{Results, Solver, SolverConfig, SOLVERS};
+use crate::utils::{KeyedStat, Stat};
+use crate::validate::{self, ErrorPolicy, Rejected};
+
+/// Largest difference allowed between two means, in degrees. The means are computed
+/// from exact fixed point sums, so only the float division may tell them apart.
+pub const MEAN_TOLERANCE: f64 = 1e-9;
+/// Thread counts the solvers are run with: one, a few, and more than some inputs
+/// have lines.
+pub const THREAD_COUNTS: [usize; 3] = [1, 3, 8];
+
+/// One input of the harness.
+pub struct Case {
+    pub name: String,
+    pub contents: Vec<u8>,
+}
+
+impl Case {
+    pub fn new(name: &str, contents: Vec<u8>) -> Case {
+        Case { name: name.to_string(), contents }
+    }
+}
+
+/// How the results of a solver differ from the expected ones for a station.
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    Missing,
+    Unexpected,
+    Field { field: &'static str, expected: String, actual: String },
+    /// The solver panicked, the station is empty.
+    Panicked(String),
+}
+
+/// The first station a solver got wrong on an input.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub solver: &'static str,
+    pub case: String,
+    pub thread_count: usize,
+    pub station: String,
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {} with {} threads: ", self.solver, self.case, self.thread_count)?;
+        match &self.mismatch {
+            Mismatch::Missing => write!(f, "station '{}' is missing", self.station),
+            Mismatch::Unexpected => write!(f, "unexpected station '{}'", self.station),
+            Mismatch::Field { field, expected, actual } => write!(f, "{} of '{}' is {} instead of {}", field, self.station, actual, expected),
+            Mismatch::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+// the integer fields of a station compared exactly at the finer of both precisions,
+// the mean within `tolerance`
+fn compare_stat(expected: &KeyedStat, actual: &KeyedStat, tolerance: f64) -> Option<Mismatch> {
+    let precision = expected.precision.max(actual.precision);
+    let (e, a) = (expected.rescaled(precision), actual.rescaled(precision));
+    let field = |field, expected: &dyn ToString, actual: &dyn ToString| Some(Mismatch::Field { field, expected: expected.to_string(), actual: actual.to_string() });
+    if e.station[..e.len] != a.station[..a.len] {
+        return field("name", &String::from_utf8_lossy(&e.station[..e.len]), &String::from_utf8_lossy(&a.station[..a.len]));
+    }
+    if e.count != a.count {
+        return field("count", &e.count, &a.count);
+    }
+    if e.min != a.min {
+        return field("min", &e.min, &a.min);
+    }
+    if e.max != a.max {
+        return field("max", &e.max, &a.max);
+    }
+    if e.sum != a.sum {
+        return field("sum", &e.sum, &a.sum);
+    }
+    if (e.mean() - a.mean()).abs() > tolerance {
+        return field("mean", &e.mean(), &a.mean());
+    }
+    None
+}
+
+/// The first station, in name order, on which `actual` differs from `expected`.
+pub fn first_difference(expected: &Results, actual: &Results, tolerance: f64) -> Option<(String, Mismatch)> {
+    let mut expected_iter = expected.iter().peekable();
+    let mut actual_iter = actual.iter().peekable();
+    loop {
+        match (expected_iter.peek(), actual_iter.peek()) {
+            (None, None) => return None,
+            (Some((station, _)), None) => return Some((station.to_string(), Mismatch::Missing)),
+            (None, Some((station, _))) => return Some((station.to_string(), Mismatch::Unexpected)),
+            (Some((e_station, e_stat)), Some((a_station, a_stat))) => match e_station.cmp(a_station) {
+                std::cmp::Ordering::Less => return Some((e_station.to_string(), Mismatch::Missing)),
+                std::cmp::Ordering::Greater => return Some((a_station.to_string(), Mismatch::Unexpected)),
+                std::cmp::Ordering::Equal => {
+                    if let Some(mismatch) = compare_stat(e_stat, a_stat, tolerance) {
+                        return Some((e_station.to_string(), mismatch));
+                    }
+                    expected_iter.next();
+                    actual_iter.next();
+                },
+            },
+        }
+    }
+}
+
+/// Aggregates `contents` line by line with `validate::validate_row`, the slow and
+/// obvious way. These are the results attempt10 must give for any input, well formed
+/// or not, under `ErrorPolicy::Skip`.
+pub fn oracle(contents: &[u8], precision: u32) -> (Results, Rejected) {
+    let mut results = Results::new();
+    let mut rejected = Rejected::default();
+    let mut offset = 0;
+    while offset < contents.len() {
+        let end = contents[offset..].iter().position(|&b| b == b'\n').map_or(contents.len(), |len| offset + len);
+        let line = &contents[offset..end];
+        match validate::validate_row(line, precision) {
+            Ok((name, temp)) => {
+                let stat = results.entry(name.to_string()).or_insert_with(|| KeyedStat {
+                    precision,
+                    ..KeyedStat::from_stat(name, &Stat { min: temp, max: temp, sum: 0, count: 0 })
+                });
+                stat.min = stat.min.min(temp);
+                stat.max = stat.max.max(temp);
+                stat.sum += temp as i64;
+                stat.count += 1;
+            },
+            Err(kind) => rejected.add(&kind, line, ErrorPolicy::Skip),
+        }
+        offset = end + 1;
+    }
+    (results, rejected)
+}
+
+/// Runs `solver` on the file `path` and compares its results with `expected`, None
+/// when they agree.
+pub fn check(solver: &dyn Solver, case: &str, path: &str, expected: &Results, thread_count: usize) -> Option<Divergence> {
+    let divergence = |station: String, mismatch| Divergence { solver: solver.name(), case: case.to_string(), thread_count, station, mismatch };
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| solver.solve(path, &SolverConfig::new(thread_count))));
+    match outcome {
+        Ok(actual) => first_difference(expected, &actual, MEAN_TOLERANCE).map(|(station, mismatch)| divergence(station, mismatch)),
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Some(divergence(String::new(), Mismatch::Panicked(message)))
+        }
+    }
+}
+
+/// Panics with the divergence if `solver` does not agree with the naive oracle of
+/// attempt1 on `path`.
+pub fn assert_agrees(solver: &dyn Solver, path: &str, thread_count: usize) {
+    let expected = attempt1::naive_btree_kstat(path);
+    if let Some(divergence) = check(solver, path, path, &expected, thread_count) {
+        panic!("{}", divergence);
+    }
+}
+
+/// Runs each of `solvers` on each of `cases` with each of `thread_counts` and returns
+/// the first divergence from the naive oracle of attempt1 of each solver on each
+/// case, in the order of the solvers and then of the cases. The cases
+/// are written to files under `dir`, which is removed afterwards.
+pub fn run_cases(solvers: &[&dyn Solver], cases: &[Case], thread_counts: &[usize], dir: &Path) -> io::Result<Vec<Divergence>> {
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for case in cases {
+        let path = dir.join(&case.name);
+        fs::write(&path, &case.contents)?;
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    let expected: Vec<Results> = paths.iter().map(|path| attempt1::naive_btree_kstat(path)).collect();
+
+    let mut divergences = Vec::new();
+    for solver in solvers {
+        for ((case, path), expected) in cases.iter().zip(&paths).zip(&expected) {
+            let first = thread_counts.iter().find_map(|&thread_count| check(*solver, &case.name, path, expected, thread_count));
+            divergences.extend(first);
+        }
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(divergences)
+}
+
+// `rows` generated rows of `stations`
+fn rows(stations: &[StationSpec], rows: u64, seed: u64) -> Vec<u8> {
+    let config = GeneratorConfig { seed, thread_count: 1, ..GeneratorConfig::new(rows) };
+    let mut contents = Vec::new();
+    generate::write_rows(stations, &config, &mut contents).expect("writing to a Vec does not fail");
+    contents
+}
+
+// Whole lines of `contents` and lines of filler stations, `len` bytes in all. `len`
+// must leave room for at least one line.
+fn fill_to(contents: &[u8], len: usize) -> Vec<u8> {
+    // the filler lines are 37 to 72 bytes long
+    const SLACK: usize = 110;
+    let mut end = 0;
+    for (idx, &byte) in contents.iter().enumerate() {
+        if byte == b'\n' && idx + 1 + SLACK <= len {
+            end = idx + 1;
+        }
+    }
+    let mut filled = contents[..end].to_vec();
+    let rest = len - end;
+    for (idx, line_len) in [rest / 3, rest / 3, rest - 2 * (rest / 3)].into_iter().enumerate() {
+        // "<name>;1.0\n"
+        let name = format!("filler{}", idx).into_bytes();
+        filled.extend(name.iter().cycle().take(line_len - 5));
+        filled.extend_from_slice(b";1.0\n");
+    }
+    assert_eq!(filled.len(), len);
+    filled
+}
+
+/// The inputs the solvers are checked on, generated from `list`: files of a few
+/// lines, sizes that are not a multiple of the thread counts nor of the chunk sizes,
+/// some sized around those chunk sizes, one-line files and files that do not end with
+/// a newline, with short, long and non-ASCII station names.
+pub fn cases(list: &[StationSpec], chunk_sizes: &[usize]) -> Vec<Case> {
+    let choose = |seed, name_length, unicode| {
+        let config = GeneratorConfig { seed, name_length, unicode, station_count: 50, ..GeneratorConfig::new(0) };
+        generate::choose_stations(list, &config)
+    };
+    let original = choose(1, NameLength::Original, 0.0);
+    let long = choose(2, NameLength::Uniform(90, 100), 0.2);
+    let short = choose(3, NameLength::Uniform(1, 3), 0.0);
+    let unicode = choose(4, NameLength::Uniform(1, 100), 1.0);
+
+    let one_line = rows(&original, 1, 5);
+    let no_newline = |contents: &[u8]| contents[..contents.len() - 1].to_vec();
+    let mut cases = vec![
+        Case::new("empty", Vec::new()),
+        Case::new("one-line", one_line.clone()),
+        Case::new("one-line-no-newline", no_newline(&one_line)),
+        Case::new("one-long-line", rows(&long[..1], 1, 6)),
+        Case::new("two-lines", rows(&original, 2, 7)),
+        Case::new("seven-lines-no-newline", no_newline(&rows(&short, 7, 8))),
+        Case::new("short-names", rows(&short, 1001, 9)),
+        Case::new("long-names", rows(&long, 997, 10)),
+        Case::new("unicode", rows(&unicode, 3001, 11)),
+        Case::new("unicode-no-newline", no_newline(&rows(&unicode, 2999, 12))),
+        Case::new("original", rows(&original, 10_007, 13)),
+    ];
+    let largest = chunk_sizes.iter().map(|&chunk_size| 2 * chunk_size + 3).max().unwrap_or(0);
+    let mut filler = Vec::new();
+    let mut filler_rows = 1024;
+    while filler.len() < largest {
+        filler = rows(&original, filler_rows, 14);
+        filler_rows *= 2;
+    }
+    for &chunk_size in chunk_sizes {
+        for len in [chunk_size - 1, chunk_size, chunk_size + 1, 2 * chunk_size + 3] {
+            cases.push(Case::new(&format!("chunk-{}-len-{}", chunk_size, len), fill_to(&filler, len)));
+        }
+    }
+    cases
+}
+
+/// `run_cases` over all the solvers and the inputs of `cases`, generated from the
+/// default station list.
+pub fn run_all(chunk_sizes: &[usize]) -> io::Result<Vec<Divergence>> {
+    let list = generate::default_stations(generate::DEFAULT_STDDEV);
+    let dir = std::env::temp_dir().join(format!("onebrc-differential-{}", std::process::id()));
+    run_cases(&SOLVERS, &cases(&list, chunk_sizes), &THREAD_COUNTS, &dir)
+}
+
+
+#[test]
+fn test_first_difference() {
+    let stat = |name: &str, min, max, sum, count| KeyedStat { min, max, sum, count, ..KeyedStat::from_stat(name, &crate::utils::Stat { min, max, sum, count }) };
+    let expected: Results = [("a", stat("a", -10, 20, 10, 2)), ("c", stat("c", 5, 5, 5, 1))].into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+    assert_eq!(first_difference(&expected, &expected, MEAN_TOLERANCE), None);
+
+    let mut actual = expected.clone();
+    actual.get_mut("c").unwrap().max = 6;
+    assert_eq!(first_difference(&expected, &actual, MEAN_TOLERANCE), Some((String::from("c"), Mismatch::Field { field: "max", expected: String::from("5"), actual: String::from("6") })));
+    actual.insert(String::from("b"), stat("b", 0, 0, 0, 1));
+    assert_eq!(first_difference(&expected, &actual, MEAN_TOLERANCE), Some((String::from("b"), Mismatch::Unexpected)));
+    actual.remove("a");
+    assert_eq!(first_difference(&expected, &actual, MEAN_TOLERANCE), Some((String::from("a"), Mismatch::Missing)));
+
+    // the same measurements with more decimals are equal
+    let mut actual = expected.clone();
+    *actual.get_mut("a").unwrap() = expected["a"].rescaled(3);
+    assert_eq!(first_difference(&expected, &actual, MEAN_TOLERANCE), None);
+}
+
+#[test]
+fn test_oracle() {
+    let (results, rejected) = oracle(b"Hamburg;12.0\n\nBulawayo;8.9\nHamburg;-3.4\n#c\nBad;row\nBulawayo;1.0", 1);
+    assert_eq!(results, crate::aggregate_bytes(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\nBulawayo;1.0", 1).unwrap());
+    assert_eq!(rejected.to_string(), "3 rows rejected (1 comment, 1 empty line, 1 invalid temperature)");
+    assert_eq!(oracle(b"", 1), (Results::new(), Rejected::default()));
+    assert_eq!(oracle(b"\n", 1).1.total(), 1);
+}
+
+#[test]
+fn test_fill_to() {
+    let filler = b"Hamburg;12.0\nBulawayo;8.9\n".repeat(20);
+    for len in [110, 111, 200, 400, filler.len()] {
+        let filled = fill_to(&filler, len);
+        assert!(filled.ends_with(b"\n"));
+        assert!(crate::validate::validate_bytes(&filled, Some(1)).is_valid(), "{}", String::from_utf8_lossy(&filled));
+    }
+}
+
+#[test]
+fn test_differential() {
+    let divergences = run_all(&[crate::attempt8::CHUNK_SIZE]).unwrap();
+    let report: Vec<String> = divergences.iter().map(|divergence| divergence.to_string()).collect();
+    assert!(report.is_empty(), "{}", report.join("\n"));
+}
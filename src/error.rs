@@ -0,0 +1,105 @@
+use std::{any::Any, fmt, io};
+
+use crate::validate::{RowError, RowErrorKind, MAX_PRECISION};
+
+/// Why an aggregation failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The input could not be opened, sized or read.
+    Io(io::Error),
+    /// The input could not be memory mapped.
+    Mmap(io::Error),
+    /// A malformed row under `ErrorPolicy::Strict`.
+    Parse(RowError),
+    /// A worker thread panicked, with the message of the panic.
+    WorkerPanic(String),
+    /// The row would add a station beyond the limit of the hash table, see
+    /// `attempt10::MAX_STATIONS`.
+    CapacityExceeded(RowError),
+    /// The number of fractional digits asked for is not between 1 and
+    /// `validate::MAX_PRECISION`.
+    InvalidPrecision(u32),
+    /// The aggregation was asked to run with no worker thread.
+    NoThreads,
+}
+
+impl Error {
+    /// Wraps the payload of a panicked worker, as returned by `JoinHandle::join`.
+    pub fn worker_panic(payload: Box<dyn Any + Send>) -> Error {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => String::from("unknown panic payload"),
+            },
+        };
+        Error::WorkerPanic(message)
+    }
+
+    /// The row that stopped the aggregation, if a row did.
+    pub fn row(&self) -> Option<&RowError> {
+        match self {
+            Error::Parse(error) | Error::CapacityExceeded(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "cannot read the input: {}", e),
+            Error::Mmap(e) => write!(f, "cannot map the input into memory: {}", e),
+            Error::Parse(e) | Error::CapacityExceeded(e) => write!(f, "{}", e),
+            Error::WorkerPanic(message) => write!(f, "a worker thread panicked: {}", message),
+            Error::InvalidPrecision(precision) => write!(f, "precision must be between 1 and {}, got {}", MAX_PRECISION, precision),
+            Error::NoThreads => write!(f, "at least one worker thread is needed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::Mmap(e) => Some(e),
+            Error::Parse(e) | Error::CapacityExceeded(e) => Some(e),
+            Error::WorkerPanic(_) | Error::InvalidPrecision(_) | Error::NoThreads => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+// the hash table reports a full table as a row error, so that the row is located like
+// any other one
+impl From<RowError> for Error {
+    fn from(error: RowError) -> Error {
+        match error.kind {
+            RowErrorKind::TooManyStations(_) => Error::CapacityExceeded(error),
+            _ => Error::Parse(error),
+        }
+    }
+}
+
+
+#[test]
+fn test_error() {
+    let row = |kind| RowError { line: 3, offset: 26, kind };
+    let error = Error::from(row(RowErrorKind::EmptyLine));
+    assert!(matches!(error, Error::Parse(_)));
+    assert_eq!(error.to_string(), "line 3 (byte offset 26): empty line");
+    let error = Error::from(row(RowErrorKind::TooManyStations(10)));
+    assert!(matches!(error, Error::CapacityExceeded(_)));
+    assert_eq!(error.row().unwrap().line, 3);
+
+    let panicked = |payload: Box<dyn Any + Send>| Error::worker_panic(payload).to_string();
+    assert_eq!(panicked(Box::new("index out of bounds")), "a worker thread panicked: index out of bounds");
+    assert_eq!(panicked(Box::new(String::from("boom"))), "a worker thread panicked: boom");
+    assert_eq!(panicked(Box::new(42)), "a worker thread panicked: unknown panic payload");
+    assert!(Error::from(io::Error::from(io::ErrorKind::NotFound)).row().is_none());
+    assert_eq!(Error::InvalidPrecision(9).to_string(), "precision must be between 1 and 6, got 9");
+}
@@ -0,0 +1,350 @@
+use std::{collections::HashSet, fs, io::{self, Write}, sync::mpsc, thread};
+
+use crate::error::Error;
+use crate::utils;
+use crate::validate::MAX_NAME_LEN;
+
+/// Station list used when none is given, the stations of the original challenge with
+/// their mean temperatures. Built in, so that it is found from any directory.
+pub const DEFAULT_STATIONS: &str = include_str!("../data/weather_stations.csv");
+/// Stations drawn from the list when not told otherwise, as many as in the original
+/// challenge.
+pub const DEFAULT_STATION_COUNT: usize = 413;
+/// Standard deviation of the stations the list gives none for, in degrees.
+pub const DEFAULT_STDDEV: f64 = 10.0;
+/// Rows generated by one task. The rows of a block only depend on the seed and the
+/// index of the block, so the output is the same whatever the number of threads.
+pub const ROWS_PER_BLOCK: u64 = 1 << 18;
+
+/// SplitMix64. Small, fast, and unlike the generators of the `rand` crates its output
+/// is fixed, so a seed gives the same file with every build.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    /// An independent generator for the `index`th stream of `seed`.
+    pub fn stream(seed: u64, index: u64) -> Rng {
+        let mut rng = Rng(seed ^ index.wrapping_mul(0xD1B54A32D192ED03));
+        Rng(rng.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, with the Box-Muller transform.
+    pub fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.unit();
+        let v = self.unit();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StationSpec {
+    pub name: String,
+    /// Mean temperature, in degrees.
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+/// Parses a station list with `name;mean[;stddev]` per line, skipping empty lines,
+/// `#` comments and the later occurrences of a name.
+pub fn parse_stations(contents: &str, default_stddev: f64) -> io::Result<Vec<StationSpec>> {
+    let mut seen = HashSet::new();
+    let mut stations = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", idx + 1, what));
+        let mut fields = line.split(';');
+        let name = fields.next().unwrap();
+        let number = |field: Option<&str>, what: &str| field.map(|value| value.trim().parse::<f64>().map_err(|_| invalid(what))).transpose();
+        let mean = number(fields.next(), "invalid mean")?.ok_or_else(|| invalid("missing mean"))?;
+        let stddev = number(fields.next(), "invalid standard deviation")?.unwrap_or(default_stddev);
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(invalid("station names must have 1 to 100 bytes"));
+        }
+        if fields.next().is_some() {
+            return Err(invalid("expected name;mean[;stddev]"));
+        }
+        if seen.insert(name) {
+            stations.push(StationSpec { name: name.to_string(), mean, stddev });
+        }
+    }
+    Ok(stations)
+}
+
+/// Parses `DEFAULT_STATIONS`.
+pub fn default_stations(default_stddev: f64) -> Vec<StationSpec> {
+    parse_stations(DEFAULT_STATIONS, default_stddev).expect("the built-in station list is valid")
+}
+
+/// Reads a station list, see `parse_stations`.
+pub fn read_stations(path: &str, default_stddev: f64) -> io::Result<Vec<StationSpec>> {
+    let contents = fs::read_to_string(path)?;
+    parse_stations(&contents, default_stddev).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+/// Length of the generated station names, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NameLength {
+    /// The names of the list as they are.
+    Original,
+    Fixed(usize),
+    /// Uniform between the two bounds, inclusive.
+    Uniform(usize, usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorConfig {
+    pub rows: u64,
+    pub seed: u64,
+    pub station_count: usize,
+    pub name_length: NameLength,
+    /// Fraction of the station names that get non-ASCII characters.
+    pub unicode: f64,
+    pub thread_count: usize,
+}
+
+impl GeneratorConfig {
+    pub fn new(rows: u64) -> GeneratorConfig {
+        GeneratorConfig {
+            rows,
+            seed: 0,
+            station_count: DEFAULT_STATION_COUNT,
+            name_length: NameLength::Original,
+            unicode: 0.0,
+            thread_count: utils::THREAD_COUNT,
+        }
+    }
+}
+
+// two, three and four byte characters
+const UNICODE_CHARS: [char; 12] = ['é', 'ü', 'ñ', 'ø', 'ß', 'Ж', 'ś', 'ı', '東', '京', 'ก', '🌍'];
+// letters appended to the names that are too short
+const FILLER: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+// Replaces about a third of the characters with non-ASCII ones.
+fn to_unicode(name: &str, rng: &mut Rng) -> String {
+    name.chars().map(|c| match rng.below(3) {
+        0 => UNICODE_CHARS[rng.below(UNICODE_CHARS.len() as u64) as usize],
+        _ => c,
+    }).collect()
+}
+
+// Cuts `name` at a character boundary or pads it with letters to exactly `len` bytes.
+fn fit_length(name: &str, len: usize, rng: &mut Rng) -> String {
+    let mut end = name.len().min(len);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut fitted = name[..end].to_string();
+    while fitted.len() < len {
+        fitted.push(FILLER[rng.below(FILLER.len() as u64) as usize] as char);
+    }
+    fitted
+}
+
+/// Draws `config.station_count` stations from `list` and reshapes their names as
+/// `config` says, the names staying distinct.
+pub fn choose_stations(list: &[StationSpec], config: &GeneratorConfig) -> Vec<StationSpec> {
+    let mut rng = Rng::new(config.seed);
+    let mut chosen = list.to_vec();
+    // a partial Fisher-Yates shuffle
+    let count = config.station_count.min(chosen.len());
+    for idx in 0..count {
+        let other = idx + rng.below((chosen.len() - idx) as u64) as usize;
+        chosen.swap(idx, other);
+    }
+    chosen.truncate(count);
+
+    let mut names = HashSet::new();
+    for (idx, station) in chosen.iter_mut().enumerate() {
+        let mut name = station.name.clone();
+        if rng.unit() < config.unicode {
+            name = to_unicode(&name, &mut rng);
+        }
+        let len = match config.name_length {
+            NameLength::Original => name.len(),
+            NameLength::Fixed(len) => len,
+            NameLength::Uniform(min, max) => min + rng.below((max - min + 1) as u64) as usize,
+        };
+        name = fit_length(&name, len.clamp(1, MAX_NAME_LEN), &mut rng);
+        if names.contains(&name) {
+            // makes it distinct with the index of the station at its end
+            let suffix = idx.to_string();
+            name = fit_length(&name, name.len().max(suffix.len()) - suffix.len(), &mut rng) + &suffix;
+        }
+        names.insert(name.clone());
+        station.name = name;
+    }
+    chosen
+}
+
+// appends a temperature in tenths of a degree, e.g. -5 => "-0.5"
+fn push_tenths(buf: &mut Vec<u8>, tenths: i32) {
+    if tenths < 0 {
+        buf.push(b'-');
+    }
+    let abs = tenths.unsigned_abs();
+    if abs >= 100 {
+        buf.push(b'0' + (abs / 100) as u8);
+    }
+    buf.extend_from_slice(&[b'0' + (abs / 10 % 10) as u8, b'.', b'0' + (abs % 10) as u8]);
+}
+
+// the rows of block `index`
+fn generate_block(stations: &[StationSpec], config: &GeneratorConfig, index: u64) -> Vec<u8> {
+    let mut rng = Rng::stream(config.seed, index);
+    let rows = ROWS_PER_BLOCK.min(config.rows - index * ROWS_PER_BLOCK);
+    let mut buf = Vec::with_capacity(rows as usize * 16);
+    for _ in 0..rows {
+        let station = &stations[rng.below(stations.len() as u64) as usize];
+        let temp = station.mean + station.stddev * rng.normal();
+        let tenths = ((temp * 10.0).round() as i32).clamp(-999, 999);
+        buf.extend_from_slice(station.name.as_bytes());
+        buf.push(b';');
+        push_tenths(&mut buf, tenths);
+        buf.push(b'\n');
+    }
+    buf
+}
+
+/// Writes `config.rows` rows of measurements of `stations`, chosen uniformly, with
+/// temperatures drawn from the normal distribution of each station and rounded to one
+/// decimal within -99.9..=99.9. The blocks of rows are generated by
+/// `config.thread_count` threads and written in order.
+pub fn write_rows<W: Write>(stations: &[StationSpec], config: &GeneratorConfig, mut out: W) -> io::Result<()> {
+    if stations.is_empty() && config.rows > 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no stations to generate rows for"));
+    }
+    let block_count = config.rows.div_ceil(ROWS_PER_BLOCK);
+    let thread_count = config.thread_count.clamp(1, block_count.max(1) as usize);
+    thread::scope(|scope| {
+        // thread k generates blocks k, k + thread_count, ..., one block ahead at most
+        let mut generators: Vec<_> = (0..thread_count).map(|k| {
+            let (sender, receiver) = mpsc::sync_channel(1);
+            let handle = scope.spawn(move || {
+                for index in (k as u64..block_count).step_by(thread_count) {
+                    // fails once the writer gave up
+                    if sender.send(generate_block(stations, config, index)).is_err() {
+                        break;
+                    }
+                }
+            });
+            (receiver, handle)
+        }).collect();
+        for index in 0..block_count {
+            let k = index as usize % thread_count;
+            let Ok(block) = generators[k].0.recv() else {
+                // the thread is gone before sending all its blocks, joining it takes its
+                // panic instead of letting the scope resume it
+                let (_, handle) = generators.swap_remove(k);
+                let error = handle.join().map_or_else(Error::worker_panic, |_| Error::WorkerPanic(String::from("a generator thread stopped early")));
+                return Err(io::Error::other(error));
+            };
+            out.write_all(&block)?;
+        }
+        out.flush()
+    })
+}
+
+
+#[cfg(test)]
+fn test_stations() -> Vec<StationSpec> {
+    default_stations(DEFAULT_STDDEV)
+}
+
+#[test]
+fn test_parse_stations() {
+    let stations = parse_stations("# comment\nHamburg;12.0\n\nBulawayo;8.9;2.5\nHamburg;13.0\n", 10.0).unwrap();
+    assert_eq!(stations, vec![
+        StationSpec { name: String::from("Hamburg"), mean: 12.0, stddev: 10.0 },
+        StationSpec { name: String::from("Bulawayo"), mean: 8.9, stddev: 2.5 },
+    ]);
+    assert_eq!(parse_stations("Hamburg\n", 10.0).unwrap_err().to_string(), "line 1: missing mean");
+    assert_eq!(parse_stations("a;1\nHamburg;warm\n", 10.0).unwrap_err().to_string(), "line 2: invalid mean");
+    assert_eq!(parse_stations(";1\n", 10.0).unwrap_err().to_string(), "line 1: station names must have 1 to 100 bytes");
+    assert_eq!(test_stations().len(), DEFAULT_STATION_COUNT);
+}
+
+#[test]
+fn test_choose_stations() {
+    let list = test_stations();
+    let mut config = GeneratorConfig::new(0);
+    let chosen = choose_stations(&list, &config);
+    assert_eq!(chosen.len(), DEFAULT_STATION_COUNT);
+    assert!(chosen.iter().all(|station| list.contains(station)));
+    assert_eq!(chosen, choose_stations(&list, &config));
+
+    // the cities of data/test.csv, the second column is their latitude but only the
+    // names matter here
+    let cities = read_stations("data/test.csv", DEFAULT_STDDEV).unwrap();
+    config.station_count = 10_000;
+    config.name_length = NameLength::Uniform(1, 100);
+    config.unicode = 0.5;
+    let chosen = choose_stations(&cities, &config);
+    let names: HashSet<&str> = chosen.iter().map(|station| station.name.as_str()).collect();
+    assert_eq!(names.len(), 10_000);
+    assert!(names.iter().all(|name| (1..=100).contains(&name.len()) && !name.contains([';', '\n'])));
+    let unicode = names.iter().filter(|name| !name.is_ascii()).count();
+    assert!((4000..6000).contains(&unicode), "{} non-ASCII names", unicode);
+
+    config.name_length = NameLength::Fixed(100);
+    assert!(choose_stations(&cities, &config).iter().all(|station| station.name.len() == 100));
+}
+
+#[test]
+fn test_write_rows() {
+    let mut config = GeneratorConfig::new(2 * ROWS_PER_BLOCK + 1000);
+    let stations = choose_stations(&test_stations(), &config);
+    let generate = |config: &GeneratorConfig| {
+        let mut out = Vec::new();
+        write_rows(&stations, config, &mut out).unwrap();
+        out
+    };
+    config.thread_count = 1;
+    let contents = generate(&config);
+    config.thread_count = 3;
+    assert!(contents == generate(&config), "the output depends on the thread count");
+    config.seed = 1;
+    assert!(contents != generate(&config));
+    assert_eq!(write_rows(&[], &config, Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let report = crate::validate::validate_bytes(&contents, Some(1));
+    assert_eq!((report.rows, report.error_count), (config.rows as usize, 0));
+    let results = crate::aggregate_bytes(&contents, 2).unwrap();
+    assert_eq!(results.values().map(|stat| stat.count).sum::<u64>(), config.rows);
+    // about 1.3k rows per station, a standard error of 0.3 degrees
+    for station in &stations {
+        let stat = &results[&station.name];
+        assert!((stat.mean() - station.mean).abs() < 1.5, "{}: {} instead of {}", station.name, stat.mean(), station.mean);
+    }
+
+    let mut buf = Vec::new();
+    for tenths in [-999, -100, -5, 0, 7, 99, 999] {
+        buf.clear();
+        push_tenths(&mut buf, tenths);
+        assert_eq!(buf, utils::format_tenths(tenths as i64).as_bytes());
+    }
+}
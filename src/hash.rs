@@ -0,0 +1,222 @@
+use std::{fmt, fs::File, io::Read, time::{Duration, Instant}};
+
+use memmap2::Mmap;
+
+use crate::compression;
+use crate::error::Error;
+use crate::schedule::{self, CHUNK_SIZE};
+use crate::validate::ErrorPolicy;
+
+/// Hash of the station names. Once the scanner of `attempt10` has found the end of a
+/// name, `hash_name_at` hashes it in place in the input, and `hash_name` hashes a name
+/// on its own. Both feed it one 8-byte little-endian word at a time, the last word
+/// zero padded, so they agree.
+pub trait NameHash {
+    /// Name of the hash in the benchmark reports.
+    const NAME: &'static str;
+    /// State before the first word.
+    const SEED: usize;
+
+    /// Mixes the next word of the name into the state.
+    fn write(state: usize, word: usize) -> usize;
+}
+
+/// The hash the tables use unless told otherwise.
+pub type DefaultHash = Djb2;
+
+/// `hash * 33 + word`, the hash the scanner started with.
+pub struct Djb2;
+
+impl NameHash for Djb2 {
+    const NAME: &'static str = "djb2";
+    const SEED: usize = 5381;
+
+    #[inline(always)]
+    fn write(state: usize, word: usize) -> usize {
+        (state << 5).wrapping_add(state).wrapping_add(word)
+    }
+}
+
+/// The hash of `rustc-hash`, as used by the `FxHashMap` of attempt4.
+pub struct Fx;
+
+impl NameHash for Fx {
+    const NAME: &'static str = "fx";
+    const SEED: usize = 0;
+
+    #[inline(always)]
+    fn write(state: usize, word: usize) -> usize {
+        (state.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95)
+    }
+}
+
+/// Multiplies by an odd constant and folds the high half of the product into the low
+/// one, so that every bit of the word reaches the low bits.
+pub struct MultiplyShift;
+
+impl NameHash for MultiplyShift {
+    const NAME: &'static str = "multiply-shift";
+    const SEED: usize = 0;
+
+    #[inline(always)]
+    fn write(state: usize, word: usize) -> usize {
+        let product = (state ^ word).wrapping_mul(0x9E3779B97F4A7C15);
+        product ^ (product >> 32)
+    }
+}
+
+/// One AES encryption round over the state and the word, in the spirit of gxhash.
+/// Only available when the target has AES-NI, e.g. with `-C target-cpu=native`.
+#[cfg(all(target_arch = "x86_64", target_feature = "aes"))]
+pub struct Aes;
+
+#[cfg(all(target_arch = "x86_64", target_feature = "aes"))]
+impl NameHash for Aes {
+    const NAME: &'static str = "aes";
+    const SEED: usize = 0;
+
+    #[inline(always)]
+    fn write(state: usize, word: usize) -> usize {
+        use std::arch::x86_64::{_mm_aesenc_si128, _mm_cvtsi128_si64, _mm_set_epi64x, _mm_srli_si128, _mm_xor_si128};
+
+        // the crate is built for a target with AES-NI, which implies SSE2
+        unsafe {
+            let block = _mm_set_epi64x(word as i64, state as i64);
+            let key = _mm_set_epi64x(0x243F6A8885A308D3, 0x13198A2E03707344);
+            let round = _mm_aesenc_si128(block, key);
+            _mm_cvtsi128_si64(_mm_xor_si128(round, _mm_srli_si128::<8>(round))) as usize
+        }
+    }
+}
+
+/// The SSE 4.2 CRC32-C instruction, 32 bits of hash for a 64-bit word. Only available
+/// when the target has SSE 4.2.
+#[cfg(all(target_arch = "x86_64", target_feature = "sse4.2"))]
+pub struct Crc32;
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse4.2"))]
+impl NameHash for Crc32 {
+    const NAME: &'static str = "crc32";
+    const SEED: usize = 0;
+
+    #[inline(always)]
+    fn write(state: usize, word: usize) -> usize {
+        // the crate is built for a target with SSE 4.2
+        unsafe { std::arch::x86_64::_mm_crc32_u64(state as u64, word as u64) as usize }
+    }
+}
+
+/// Hashes a whole name, as `attempt10::hash_name_at` does in place.
+pub fn hash_name<H: NameHash>(name: &[u8]) -> usize {
+    name.chunks(8).fold(H::SEED, |state, chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        H::write(state, usize::from_le_bytes(word))
+    })
+}
+
+/// How one hash fared on an input.
+#[derive(Debug)]
+pub struct HashReport {
+    pub name: &'static str,
+    pub bytes: usize,
+    pub elapsed: Duration,
+    /// Table lookups, one per valid row.
+    pub lookups: usize,
+    /// Occupied slots probed before the one of the station, over all the lookups.
+    pub collisions: usize,
+}
+
+impl HashReport {
+    /// Megabytes of input aggregated per second.
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / 1e6 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for HashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<15} {:>12} collisions / {:>12} lookups, {:>8} ms, {:>9.1} MB/s",
+            self.name, self.collisions, self.lookups, self.elapsed.as_millis(), self.throughput())
+    }
+}
+
+/// Aggregates `contents` with attempt10 and the hash `H`. Malformed rows are skipped,
+/// fails if a worker panics.
+pub fn benchmark<H: NameHash>(contents: &[u8], thread_count: usize) -> Result<HashReport, Error> {
+    let start_time = Instant::now();
+    let workers = schedule::distribute_work::<H>(&[contents], thread_count, ErrorPolicy::Skip, None, CHUNK_SIZE)?;
+    let elapsed = start_time.elapsed();
+    Ok(HashReport {
+        name: H::NAME,
+        bytes: contents.len(),
+        elapsed,
+        lookups: workers.iter().map(|worker| worker.chunk.table.insert_count()).sum(),
+        collisions: workers.iter().map(|worker| worker.chunk.table.collision_count()).sum(),
+    })
+}
+
+/// Runs `benchmark` with each of the hashes the target supports.
+pub fn benchmark_all(contents: &[u8], thread_count: usize) -> Result<Vec<HashReport>, Error> {
+    #[allow(unused_mut)]
+    let mut reports = vec![
+        benchmark::<Djb2>(contents, thread_count)?,
+        benchmark::<Fx>(contents, thread_count)?,
+        benchmark::<MultiplyShift>(contents, thread_count)?,
+    ];
+    #[cfg(all(target_arch = "x86_64", target_feature = "aes"))]
+    reports.push(benchmark::<Aes>(contents, thread_count)?);
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse4.2"))]
+    reports.push(benchmark::<Crc32>(contents, thread_count)?);
+    Ok(reports)
+}
+
+/// `benchmark_all` over a file, compressed files are decompressed in memory first.
+pub fn benchmark_file(path: &str, thread_count: usize) -> Result<Vec<HashReport>, Error> {
+    if let Some(mut reader) = compression::open(path, thread_count)? {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        return benchmark_all(&contents, thread_count);
+    }
+    let contents = unsafe { Mmap::map(&File::open(path)?) }.map_err(Error::Mmap)?;
+    benchmark_all(&contents, thread_count)
+}
+
+
+#[test]
+fn test_hash_name() {
+    assert_eq!(hash_name::<Djb2>(b""), 5381);
+    assert_eq!(hash_name::<Djb2>(b"ab"), 5381 * 33 + 0x6261);
+    assert_ne!(hash_name::<Fx>(b"Hamburg"), hash_name::<Fx>(b"Hamburh"));
+    assert_ne!(hash_name::<MultiplyShift>(b"Bulawayo"), hash_name::<MultiplyShift>(b"Bulawayo Bulawayo"));
+}
+
+#[cfg(test)]
+fn assert_same_results<H: NameHash>(contents: &[u8], expected: &crate::Results) {
+    for thread_count in [1, 3] {
+        let workers = schedule::distribute_work::<H>(&[contents], thread_count, ErrorPolicy::Strict, None, 1000).unwrap();
+        assert_eq!(&schedule::merge_workers(workers, 1).remove(0).unwrap().0, expected, "{} with {} threads", H::NAME, thread_count);
+    }
+}
+
+#[test]
+fn test_hashes() {
+    let contents = std::fs::read("data/test_small.csv").unwrap();
+    let expected = crate::aggregate_bytes(&contents, 1).unwrap();
+    assert_same_results::<Fx>(&contents, &expected);
+    assert_same_results::<MultiplyShift>(&contents, &expected);
+    #[cfg(all(target_arch = "x86_64", target_feature = "aes"))]
+    assert_same_results::<Aes>(&contents, &expected);
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse4.2"))]
+    assert_same_results::<Crc32>(&contents, &expected);
+}
+
+#[test]
+fn test_benchmark() {
+    let contents = std::fs::read("data/test_small.csv").unwrap();
+    let expected = crate::aggregate_bytes(&contents, 1).unwrap();
+    for report in benchmark_all(&contents, 2).unwrap() {
+        assert_eq!(report.bytes, contents.len());
+        assert_eq!(report.lookups, expected.values().map(|stat| stat.count as usize).sum::<usize>(), "{}", report.name);
+    }
+}
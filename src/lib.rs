@@ -0,0 +1,180 @@
+//! Aggregation engine for the one billion row challenge: computes the min, mean and max
+//! temperature per weather station from `<station>;<temperature>` lines.
+//!
+//! The functions at the root of the crate are the stable entry points and run the
+//! attempt10 engine. The other strategies explored while optimizing the problem are
+//! available as `Solver`s, see `solver::SOLVERS`.
+
+use std::io::{self, Read};
+
+// internals of the binaries, benches and fuzz targets, not part of the API
+#[doc(hidden)]
+pub mod utils;
+#[doc(hidden)]
+pub mod arena;
+#[doc(hidden)]
+pub mod hash;
+#[doc(hidden)]
+pub mod scan;
+#[doc(hidden)]
+pub mod schedule;
+#[doc(hidden)]
+pub mod output;
+#[doc(hidden)]
+pub mod validate;
+#[doc(hidden)]
+pub mod compression;
+#[doc(hidden)]
+pub mod generate;
+#[doc(hidden)]
+pub mod differential;
+
+pub mod solver;
+mod error;
+mod stream;
+mod attempt1;
+mod attempt2;
+mod attempt3;
+mod attempt4;
+mod attempt5;
+mod attempt6;
+mod attempt7;
+mod attempt8;
+mod attempt9;
+mod attempt10;
+
+pub use error::Error;
+pub use solver::{Results, Solver, SolverConfig};
+pub use utils::KeyedStat;
+pub use validate::{ErrorPolicy, Rejected, RowError, RowErrorKind};
+
+
+/// Aggregates the measurements file at `path` using `thread_count` worker threads.
+/// Fails if the file cannot be read or on the first malformed row, see
+/// `aggregate_file_with`.
+pub fn aggregate_file(path: &str, thread_count: usize) -> Result<Results, Error> {
+    attempt10::run(path, thread_count)
+}
+
+/// Aggregates the measurements file at `path`, handling malformed rows according to
+/// `config.policy`. Returns the rows that were rejected along with the results.
+pub fn aggregate_file_with(path: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+    attempt10::run_with_options(path, config.thread_count, config.policy, config.precision)
+}
+
+/// Aggregates several measurements files into a single result, the threads share the
+/// work of all the files. Fails if a file cannot be read or on the first malformed
+/// row, see `aggregate_files_with`.
+pub fn aggregate_files(paths: &[&str], thread_count: usize) -> Result<Results, Error> {
+    let mut merged = Results::new();
+    for outcome in aggregate_files_with(paths, &SolverConfig::new(thread_count)) {
+        utils::merge_results(&mut merged, outcome?.0);
+    }
+    Ok(merged)
+}
+
+/// Aggregates several measurements files, handling malformed rows according to
+/// `config.policy`. Returns the results of each file, see `utils::merge_results` to
+/// combine them.
+pub fn aggregate_files_with(paths: &[&str], config: &SolverConfig) -> Vec<Result<(Results, Rejected), Error>> {
+    attempt10::run_files(paths, config.thread_count, config.policy, config.precision)
+}
+
+/// Expands a glob pattern such as `data/*.txt` into the matching paths, in
+/// alphabetical order. A path without wildcards is returned as is, even if it does
+/// not exist, and a pattern that matches nothing is an error.
+pub fn expand_glob(pattern: &str) -> io::Result<Vec<String>> {
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![pattern.to_string()]);
+    }
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let mut paths = Vec::new();
+    for entry in glob::glob(pattern).map_err(|e| invalid(format!("invalid pattern '{}': {}", pattern, e)))? {
+        let path = entry.map_err(io::Error::from)?;
+        paths.push(path.to_str().ok_or_else(|| invalid(format!("{} is not valid UTF-8", path.display())))?.to_string());
+    }
+    if paths.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no file matches '{}'", pattern)));
+    }
+    Ok(paths)
+}
+
+/// Aggregates measurements that are already in memory. Fails on the first malformed
+/// row, see `aggregate_bytes_with`.
+pub fn aggregate_bytes(contents: &[u8], thread_count: usize) -> Result<Results, Error> {
+    aggregate_bytes_with(contents, &SolverConfig::new(thread_count)).map(|(results, _)| results)
+}
+
+/// Aggregates measurements that are already in memory, handling malformed rows
+/// according to `config.policy`.
+pub fn aggregate_bytes_with(contents: &[u8], config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+    schedule::run(&[contents], config.thread_count, config.policy, config.precision).remove(0)
+}
+
+/// Aggregates measurements read from `reader`, such as stdin, as they are streamed
+/// in. Fails with `Error::Parse` on the first malformed row.
+pub fn aggregate_reader<R: Read>(reader: R, thread_count: usize) -> Result<Results, Error> {
+    aggregate_reader_with(reader, &SolverConfig::new(thread_count)).map(|(results, _)| results)
+}
+
+/// Aggregates measurements read from `reader`, handling malformed rows according to
+/// `config.policy`.
+pub fn aggregate_reader_with<R: Read>(reader: R, config: &SolverConfig) -> Result<(Results, Rejected), Error> {
+    stream::aggregate(reader, config.thread_count, config.policy, config.precision)
+}
+
+
+#[test]
+fn test_aggregate_inputs() {
+    let path = "data/test_small.csv";
+    let expected = aggregate_file(path, 3).unwrap();
+    let contents = std::fs::read(path).unwrap();
+    assert_eq!(aggregate_bytes(&contents, 3).unwrap(), expected);
+    assert_eq!(aggregate_reader(contents.as_slice(), 3).unwrap(), expected);
+}
+
+#[test]
+fn test_aggregate_files() {
+    let paths = ["data/test_small.csv", "data/samples/measurements-1.txt", "data/samples/measurements-unicode.txt", "data/test_small.csv"];
+    let mut expected = Results::new();
+    for path in paths {
+        utils::merge_results(&mut expected, aggregate_file(path, 1).unwrap());
+    }
+    for thread_count in [1, 2, 7, 64] {
+        assert_eq!(aggregate_files(&paths, thread_count).unwrap(), expected, "{} threads", thread_count);
+        let outcomes = aggregate_files_with(&paths, &SolverConfig::new(thread_count));
+        for (path, outcome) in paths.iter().zip(outcomes) {
+            assert_eq!(outcome.unwrap().0, aggregate_file(path, 1).unwrap());
+        }
+    }
+
+    assert_eq!(expand_glob("data/test_small.csv").unwrap(), vec!["data/test_small.csv"]);
+    assert_eq!(expand_glob("data/test*.csv").unwrap(), vec!["data/test.csv", "data/test_small.csv"]);
+    assert_eq!(expand_glob("data/samples/*.txt").unwrap().len(), 3);
+    assert_eq!(expand_glob("data/*.nothing").unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_invalid_config() {
+    let path = "data/test_small.csv";
+    let contents = std::fs::read(path).unwrap();
+    for precision in [0, validate::MAX_PRECISION + 1] {
+        let config = SolverConfig { precision: Some(precision), ..SolverConfig::new(2) };
+        let invalid = |outcome: Result<(), Error>| matches!(outcome, Err(Error::InvalidPrecision(p)) if p == precision);
+        assert!(invalid(aggregate_file_with(path, &config).map(drop)));
+        assert!(invalid(aggregate_files_with(&[path, path], &config).remove(1).map(drop)));
+        assert!(invalid(aggregate_bytes_with(&contents, &config).map(drop)));
+        assert!(invalid(aggregate_bytes_with(b"", &config).map(drop)));
+        assert!(invalid(aggregate_reader_with(contents.as_slice(), &config).map(drop)));
+        assert!(invalid(schedule::distribute_work::<hash::DefaultHash>(&[&contents], 2, config.policy, config.precision, 64).map(drop)));
+    }
+
+    let config = SolverConfig::new(0);
+    let no_threads = |outcome: Result<(), Error>| matches!(outcome, Err(Error::NoThreads));
+    assert!(no_threads(aggregate_file_with(path, &config).map(drop)));
+    assert!(no_threads(aggregate_files_with(&[path, path], &config).remove(1).map(drop)));
+    assert!(no_threads(aggregate_bytes_with(&contents, &config).map(drop)));
+    assert!(no_threads(aggregate_reader_with(contents.as_slice(), &config).map(drop)));
+    assert!(no_threads(attempt10::Attempt10.try_solve(path, &config).map(drop)));
+    assert!(no_threads(schedule::distribute_work::<hash::DefaultHash>(&[&contents], 0, config.policy, None, 64).map(drop)));
+}
@@ -1,33 +1,212 @@
-#![feature(stdarch_x86_avx512)]
-#![feature(portable_simd)]
-use std::time::Instant;
+use std::{env, fs::File, io::{self, BufWriter, Read, Write}, process, time::Instant};
 
 
-mod utils;
-mod attempt1;
-mod attempt2;
-mod attempt3;
-mod attempt4;
-mod attempt5;
-mod attempt6;
+mod cli;
 
-mod attempt7;
-mod attempt8;
-mod attempt9;
-mod attempt10;
+use log::info;
+use onebrc::{compression, hash, output, utils, validate, Error, ErrorPolicy, Results};
+use onebrc::solver::{self, SolverConfig};
 
 
-use log::{debug, info};
-
-use crate::utils::THREAD_COUNT;
+fn main() {
+    env_logger::init();
 
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(cli::CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if args.list {
+        for solver in solver::SOLVERS {
+            println!("{:<10} {}", solver.name(), solver.description());
+        }
+        return;
+    }
+    let solver = match solver::find(&args.implementation) {
+        Some(solver) => solver,
+        None => {
+            eprintln!("error: unknown implementation '{}', expected one of {}", args.implementation, solver::names().join(", "));
+            process::exit(2);
+        }
+    };
+    let format = match output::find(&args.format) {
+        Some(format) => format,
+        None => {
+            eprintln!("error: unknown output format '{}', expected one of {}", args.format, output::names().join(", "));
+            process::exit(2);
+        }
+    };
 
+    let mut inputs = Vec::new();
+    for pattern in &args.inputs {
+        match onebrc::expand_glob(pattern) {
+            Ok(paths) => inputs.extend(paths),
+            Err(e) => {
+                let e = Error::Io(e);
+                eprintln!("error: {}", e);
+                process::exit(cli::exit_code(&e));
+            }
+        }
+    }
 
-fn main() {
-    env_logger::init();
+    // everything is written to memory first, the output file is only created once the
+    // run succeeded, so that a failed run leaves it untouched
+    let mut out = Vec::new();
+    if args.validate || args.bench_hashes {
+        let mut valid = true;
+        for path in &inputs {
+            let outcome = if args.validate {
+                validate_input(path, args.precision, &mut out).map(|input_valid| valid &= input_valid)
+            } else {
+                bench_hashes(path, args.threads, &mut out)
+            };
+            if let Err(e) = outcome {
+                eprintln!("error: {}: {}", input_name(path), e);
+                process::exit(cli::exit_code(&e));
+            }
+        }
+        if let Err(e) = write_output(args.output.as_deref(), &out) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        process::exit(if valid { 0 } else { 1 });
+    }
 
+    if args.precision.is_some() && !solver.supports_precision() {
+        eprintln!("error: --precision is not supported by {}, only by attempt10", solver.name());
+        process::exit(2);
+    }
+    if args.policy != ErrorPolicy::Strict && !solver.supports_error_policies() {
+        eprintln!("error: --on-error is not supported by {}, only by attempt10", solver.name());
+        process::exit(2);
+    }
+    let config = SolverConfig { thread_count: args.threads, policy: args.policy, precision: args.precision };
+    info!("Running {} with {} threads", solver.name(), config.thread_count);
     let start_time = Instant::now();
-    attempt10::run("data/measurements.txt", THREAD_COUNT);
+    // stdin cannot be mapped and compressed files are decompressed on the fly, both go
+    // through the streaming aggregator. The other files are solved together, so that
+    // the threads share the work of all of them.
+    let mut outcomes: Vec<_> = inputs.iter().map(|path| {
+        if path == cli::STDIN_INPUT {
+            return Some(onebrc::aggregate_reader_with(io::stdin().lock(), &config));
+        }
+        match compression::open(path, config.thread_count) {
+            Ok(Some(reader)) => Some(onebrc::aggregate_reader_with(reader, &config)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }).collect();
+    let mapped: Vec<usize> = (0..inputs.len()).filter(|&idx| outcomes[idx].is_none()).collect();
+    let mapped_paths: Vec<&str> = mapped.iter().map(|&idx| inputs[idx].as_str()).collect();
+    for (idx, outcome) in mapped.into_iter().zip(solver.try_solve_files(&mapped_paths, &config)) {
+        outcomes[idx] = Some(outcome);
+    }
+
+    let mut merged = Results::new();
+    for (path, outcome) in inputs.iter().zip(outcomes) {
+        let name = input_name(path);
+        let result = match outcome.expect("every input was solved") {
+            Ok((result, rejected)) => {
+                if config.policy != ErrorPolicy::Strict {
+                    eprintln!("{}: {}", name, rejected);
+                }
+                if config.policy == ErrorPolicy::Quarantine {
+                    let quarantine = format!("{}.rejected", name);
+                    if let Err(e) = File::create(&quarantine).and_then(|mut file| rejected.write_lines(&mut file)) {
+                        eprintln!("error: cannot write the rejected rows to '{}': {}", quarantine, e);
+                        process::exit(1);
+                    }
+                    if rejected.dropped > 0 {
+                        eprintln!("{}: {} rejected rows not written to '{}', past its first {} bytes", name, rejected.dropped, quarantine, validate::MAX_QUARANTINED_BYTES);
+                    }
+                }
+                result
+            },
+            Err(e) => {
+                eprintln!("error: {}: {}", name, e);
+                process::exit(cli::exit_code(&e));
+            }
+        };
+        if args.per_file {
+            let breakdown = format!("{}.{}", name, format.extension());
+            if let Err(e) = File::create(&breakdown).and_then(|file| format.write(&result, &mut BufWriter::new(file))) {
+                eprintln!("error: cannot write the results to '{}': {}", breakdown, e);
+                process::exit(1);
+            }
+        }
+        utils::merge_results(&mut merged, result);
+    }
+    if let Err(e) = format.write(&merged, &mut out).and_then(|_| write_output(args.output.as_deref(), &out)) {
+        eprintln!("error: failed to write the results: {}", e);
+        process::exit(1);
+    }
     let end_time = Instant::now();
     info!("Runtime: {} milliseconds", (end_time-start_time).as_millis());
 }
+
+// The name of an input in the messages.
+fn input_name(path: &str) -> &str {
+    if path == cli::STDIN_INPUT { "stdin" } else { path }
+}
+
+// Writes `contents` to the file at `path`, or to stdout when None.
+fn write_output(path: Option<&str>, contents: &[u8]) -> io::Result<()> {
+    match path {
+        Some(path) => {
+            let mut file = File::create(path).map_err(|e| io::Error::new(e.kind(), format!("cannot create output file '{}': {}", path, e)))?;
+            file.write_all(contents)
+        },
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(contents)?;
+            stdout.flush()
+        },
+    }
+}
+
+// Reads stdin to the end, it can be neither mapped nor read twice.
+fn read_stdin() -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    io::stdin().lock().read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// Reports the malformed rows of an input, returns whether all of them are valid.
+fn validate_input(path: &str, precision: Option<u32>, out: &mut dyn Write) -> Result<bool, Error> {
+    let report = if path == cli::STDIN_INPUT {
+        validate::validate_bytes(&read_stdin()?, precision)
+    } else {
+        if let Some(compression) = compression::detect_file(path)? {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::Unsupported, format!("{} input cannot be validated", compression.name()))));
+        }
+        validate::validate_file(path, precision)?
+    };
+    let name = input_name(path);
+    for error in &report.errors {
+        writeln!(out, "{}: {}", name, error)?;
+    }
+    if report.error_count > report.errors.len() {
+        writeln!(out, "{}: {} more errors not shown", name, report.error_count - report.errors.len())?;
+    }
+    writeln!(out, "{}: {} rows, {} errors", name, report.rows, report.error_count)?;
+    Ok(report.is_valid())
+}
+
+// Reports the collisions and throughput of each station name hash on an input.
+fn bench_hashes(path: &str, thread_count: usize, out: &mut dyn Write) -> Result<(), Error> {
+    let reports = if path == cli::STDIN_INPUT {
+        hash::benchmark_all(&read_stdin()?, thread_count)?
+    } else {
+        hash::benchmark_file(path, thread_count)?
+    };
+    for report in reports {
+        writeln!(out, "{}: {}", input_name(path), report)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::io::{self, Write};
+
+use crate::solver::Results;
+use crate::utils::{format_fixed, print_result_btreemap_kstat, KeyedStat};
+
+/// Serializes the per-station results, e.g. for downstream jobs that need a machine
+/// readable format instead of the challenge output.
+pub trait OutputFormat: Sync {
+    fn name(&self) -> &'static str;
+    /// File extension for the format, without the dot.
+    fn extension(&self) -> &'static str;
+    fn write(&self, results: &Results, out: &mut dyn Write) -> io::Result<()>;
+}
+
+#[cfg(feature = "arrow")]
+pub static FORMATS: [&dyn OutputFormat; 5] = [&Text, &Json, &Csv, &Ndjson, &ArrowIpc];
+#[cfg(not(feature = "arrow"))]
+pub static FORMATS: [&dyn OutputFormat; 4] = [&Text, &Json, &Csv, &Ndjson];
+
+pub fn find(name: &str) -> Option<&'static dyn OutputFormat> {
+    FORMATS.iter().copied().find(|format| format.name() == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    FORMATS.iter().map(|format| format.name()).collect()
+}
+
+
+/// The official challenge format: {<station>=<min>/<mean>/<max>, ...}
+pub struct Text;
+
+impl OutputFormat for Text {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extension(&self) -> &'static str {
+        "txt"
+    }
+
+    fn write(&self, results: &Results, out: &mut dyn Write) -> io::Result<()> {
+        print_result_btreemap_kstat(results, out)
+    }
+}
+
+
+fn write_json_string(out: &mut dyn Write, value: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+// min, mean and max with the decimals of the input, like in the text output
+fn format_stat(stat: &KeyedStat) -> (String, String, String) {
+    (format_fixed(stat.min.into(), stat.precision), format_fixed(stat.mean_scaled(), stat.precision), format_fixed(stat.max.into(), stat.precision))
+}
+
+// temperatures are written as numbers, formatted like in the text output
+fn write_json_object(out: &mut dyn Write, station: &str, stat: &KeyedStat) -> io::Result<()> {
+    let (min, mean, max) = format_stat(stat);
+    write!(out, "{{\"station\":")?;
+    write_json_string(out, station)?;
+    write!(out, ",\"min\":{},\"mean\":{},\"max\":{},\"count\":{}}}", min, mean, max, stat.count)
+}
+
+/// A JSON array with one object per station.
+pub struct Json;
+
+impl OutputFormat for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn write(&self, results: &Results, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "[")?;
+        for (idx, (station, stat)) in results.iter().enumerate() {
+            if idx > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "\n  ")?;
+            write_json_object(out, station, stat)?;
+        }
+        writeln!(out, "\n]")
+    }
+}
+
+/// Newline delimited JSON, one object per line.
+pub struct Ndjson;
+
+impl OutputFormat for Ndjson {
+    fn name(&self) -> &'static str {
+        "ndjson"
+    }
+
+    fn extension(&self) -> &'static str {
+        "ndjson"
+    }
+
+    fn write(&self, results: &Results, out: &mut dyn Write) -> io::Result<()> {
+        for (station, stat) in results {
+            write_json_object(out, station, stat)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// RFC 4180 CSV with a header row.
+pub struct Csv;
+
+fn write_csv_field(out: &mut dyn Write, value: &str) -> io::Result<()> {
+    if value.contains([',', '"', '\n', '\r']) {
+        write!(out, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        write!(out, "{}", value)
+    }
+}
+
+impl OutputFormat for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extension(&self) -> &'static str {
+        "csv"
+    }
+
+    fn write(&self, results: &Results, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "station,min,mean,max,count\r\n")?;
+        for (station, stat) in results {
+            let (min, mean, max) = format_stat(stat);
+            write_csv_field(out, station)?;
+            write!(out, ",{},{},{},{}\r\n", min, mean, max, stat.count)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Arrow IPC file (Feather v2) with a single record batch, available with the
+/// `arrow` feature.
+#[cfg(feature = "arrow")]
+pub struct ArrowIpc;
+
+#[cfg(feature = "arrow")]
+impl OutputFormat for ArrowIpc {
+    fn name(&self) -> &'static str {
+        "arrow"
+    }
+
+    fn extension(&self) -> &'static str {
+        "arrow"
+    }
+
+    fn write(&self, results: &Results, out: &mut dyn Write) -> io::Result<()> {
+        use std::sync::Arc;
+        use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+        use arrow_ipc::writer::FileWriter;
+        use arrow_schema::{DataType, Field, Schema};
+        use crate::utils::scale;
+
+        let to_io = |e: arrow_schema::ArrowError| io::Error::other(e);
+        let degrees = |value: i64, stat: &KeyedStat| value as f64 / scale(stat.precision) as f64;
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("station", DataType::Utf8, false),
+            Field::new("min", DataType::Float64, false),
+            Field::new("mean", DataType::Float64, false),
+            Field::new("max", DataType::Float64, false),
+            Field::new("count", DataType::UInt64, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(results.keys())),
+            Arc::new(Float64Array::from_iter_values(results.values().map(|stat| degrees(stat.min.into(), stat)))),
+            Arc::new(Float64Array::from_iter_values(results.values().map(|stat| degrees(stat.mean_scaled(), stat)))),
+            Arc::new(Float64Array::from_iter_values(results.values().map(|stat| degrees(stat.max.into(), stat)))),
+            Arc::new(UInt64Array::from_iter_values(results.values().map(|stat| stat.count))),
+        ];
+        let batch = RecordBatch::try_new(schema.clone(), columns).map_err(to_io)?;
+
+        let mut writer = FileWriter::try_new(out, &schema).map_err(to_io)?;
+        writer.write(&batch).map_err(to_io)?;
+        writer.finish().map_err(to_io)
+    }
+}
+
+
+#[cfg(test)]
+fn sample_results() -> Results {
+    use crate::utils::{to_btreemap_kstat, Stat};
+    to_btreemap_kstat([
+        (String::from("Abéché"), Stat { min: -5, max: 300, sum: 295, count: 2 }),
+        (String::from("Washington, \"D.C.\""), Stat { min: 123, max: 123, sum: 123, count: 1 }),
+    ])
+}
+
+#[cfg(test)]
+fn render(format: &str, results: &Results) -> String {
+    let mut out = Vec::new();
+    find(format).unwrap().write(results, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_text() {
+    assert_eq!(render("text", &sample_results()), "{Abéché=-0.5/14.8/30.0, Washington, \"D.C.\"=12.3/12.3/12.3}\n");
+}
+
+#[test]
+fn test_json() {
+    assert_eq!(render("json", &sample_results()), "[
+  {\"station\":\"Abéché\",\"min\":-0.5,\"mean\":14.8,\"max\":30.0,\"count\":2},
+  {\"station\":\"Washington, \\\"D.C.\\\"\",\"min\":12.3,\"mean\":12.3,\"max\":12.3,\"count\":1}
+]\n");
+    assert_eq!(render("json", &Results::new()), "[\n]\n");
+}
+
+#[test]
+fn test_ndjson() {
+    assert_eq!(render("ndjson", &sample_results()), "\
+{\"station\":\"Abéché\",\"min\":-0.5,\"mean\":14.8,\"max\":30.0,\"count\":2}
+{\"station\":\"Washington, \\\"D.C.\\\"\",\"min\":12.3,\"mean\":12.3,\"max\":12.3,\"count\":1}
+");
+}
+
+#[test]
+fn test_csv() {
+    assert_eq!(render("csv", &sample_results()), "station,min,mean,max,count\r
+Abéché,-0.5,14.8,30.0,2\r
+\"Washington, \"\"D.C.\"\"\",12.3,12.3,12.3,1\r
+");
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn test_arrow() {
+    use arrow_array::{Float64Array, StringArray};
+    use arrow_ipc::reader::FileReader;
+
+    let mut out = Vec::new();
+    find("arrow").unwrap().write(&sample_results(), &mut out).unwrap();
+    let mut reader = FileReader::try_new(std::io::Cursor::new(out), None).unwrap();
+    let batch = reader.next().unwrap().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    let stations = batch.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(stations.value(0), "Abéché");
+    let means = batch.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+    assert_eq!(means.value(0), 14.8);
+}
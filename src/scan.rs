@@ -0,0 +1,260 @@
+use std::sync::OnceLock;
+
+/// Bytes covered by one pair of masks.
+pub const BLOCK_SIZE: usize = 64;
+
+/// Positions of the delimiters in a block: bit i is set when byte i is one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockMasks {
+    pub semicolons: u64,
+    pub newlines: u64,
+}
+
+/// How the masks of a block are computed. The SIMD kernels need the CPU to support
+/// them, which `Kernel::detect` checks at run time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kernel {
+    /// One 64-byte comparison per delimiter.
+    Avx512,
+    /// Two 32-byte comparisons per delimiter.
+    Avx2,
+    /// Eight 8-byte words, with the bit tricks the scanners used before.
+    Swar,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 3] = [Kernel::Avx512, Kernel::Avx2, Kernel::Swar];
+
+    /// The widest kernel the CPU supports, detected on the first call.
+    pub fn detect() -> Kernel {
+        static KERNEL: OnceLock<Kernel> = OnceLock::new();
+        *KERNEL.get_or_init(|| Kernel::ALL.into_iter().find(|kernel| kernel.is_supported()).unwrap_or(Kernel::Swar))
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            Kernel::Swar => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::Avx512 => "avx512",
+            Kernel::Avx2 => "avx2",
+            Kernel::Swar => "swar",
+        }
+    }
+
+    /// Masks of `block`. Panics if the CPU does not support the kernel.
+    pub fn masks(&self, block: &[u8; BLOCK_SIZE]) -> BlockMasks {
+        assert!(self.is_supported(), "the CPU does not support {}", self.name());
+        unsafe { self.masks_unchecked(block) }
+    }
+
+    // Safety: the kernel must be supported.
+    #[inline]
+    unsafe fn masks_unchecked(&self, block: &[u8; BLOCK_SIZE]) -> BlockMasks {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => x86::avx512_masks(block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => x86::avx2_masks(block),
+            _ => swar_masks(block),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{BlockMasks, BLOCK_SIZE};
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    pub unsafe fn avx512_masks(block: &[u8; BLOCK_SIZE]) -> BlockMasks {
+        let bytes = _mm512_loadu_si512(block.as_ptr() as *const _);
+        BlockMasks {
+            semicolons: _mm512_cmpeq_epi8_mask(bytes, _mm512_set1_epi8(b';' as i8)),
+            newlines: _mm512_cmpeq_epi8_mask(bytes, _mm512_set1_epi8(b'\n' as i8)),
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn avx2_masks(block: &[u8; BLOCK_SIZE]) -> BlockMasks {
+        let mut masks = BlockMasks::default();
+        for half in 0..2 {
+            let bytes = _mm256_loadu_si256(block.as_ptr().add(32 * half) as *const __m256i);
+            let semicolons = _mm256_movemask_epi8(_mm256_cmpeq_epi8(bytes, _mm256_set1_epi8(b';' as i8))) as u32;
+            let newlines = _mm256_movemask_epi8(_mm256_cmpeq_epi8(bytes, _mm256_set1_epi8(b'\n' as i8))) as u32;
+            masks.semicolons |= (semicolons as u64) << (32 * half);
+            masks.newlines |= (newlines as u64) << (32 * half);
+        }
+        masks
+    }
+}
+
+// One bit per byte of the word equal to `byte`. Unlike the borrow based test, which
+// may flag the bytes above the first match, every byte is exact.
+fn byte_mask(word: u64, byte: u8) -> u64 {
+    const LOW_BITS: u64 = 0x7F7F7F7F7F7F7F7F;
+    let x = word ^ (0x0101010101010101 * byte as u64);
+    // the high bit of each byte of x that is zero
+    let zeros = !(((x & LOW_BITS).wrapping_add(LOW_BITS)) | x | LOW_BITS);
+    // gathers the 8 high bits into the top byte
+    (zeros >> 7).wrapping_mul(0x0102040810204080) >> 56
+}
+
+fn swar_masks(block: &[u8; BLOCK_SIZE]) -> BlockMasks {
+    let mut masks = BlockMasks::default();
+    for (idx, word) in block.chunks_exact(8).enumerate() {
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        masks.semicolons |= byte_mask(word, b';') << (8 * idx);
+        masks.newlines |= byte_mask(word, b'\n') << (8 * idx);
+    }
+    masks
+}
+
+/// Finds the delimiters of a buffer a block at a time. The blocks start at multiples
+/// of `BLOCK_SIZE` from the start of the buffer and their masks are computed when a
+/// search first reaches them, so the lines of a block share a single computation.
+pub struct Delimiters<'a> {
+    contents: &'a [u8],
+    kernel: Kernel,
+    block_start: usize,
+    masks: BlockMasks,
+}
+
+impl<'a> Delimiters<'a> {
+    pub fn new(contents: &'a [u8]) -> Delimiters<'a> {
+        Delimiters::with_kernel(contents, Kernel::detect())
+    }
+
+    /// Panics if the CPU does not support `kernel`.
+    pub fn with_kernel(contents: &'a [u8], kernel: Kernel) -> Delimiters<'a> {
+        assert!(kernel.is_supported(), "the CPU does not support {}", kernel.name());
+        Delimiters { contents, kernel, block_start: usize::MAX, masks: BlockMasks::default() }
+    }
+
+    /// Offset of the first ';' or '\n' at or after `from`, the length of the buffer if
+    /// there is none.
+    #[inline]
+    pub fn next_delimiter(&mut self, from: usize) -> usize {
+        self.find(from, |masks| masks.semicolons | masks.newlines)
+    }
+
+    /// Offset of the first '\n' at or after `from`, the length of the buffer if there
+    /// is none.
+    #[inline]
+    pub fn next_newline(&mut self, from: usize) -> usize {
+        self.find(from, |masks| masks.newlines)
+    }
+
+    #[inline(always)]
+    fn find(&mut self, mut from: usize, select: impl Fn(&BlockMasks) -> u64) -> usize {
+        while from < self.contents.len() {
+            let block_start = from & !(BLOCK_SIZE - 1);
+            if block_start != self.block_start {
+                self.load(block_start);
+            }
+            let mask = select(&self.masks) & (u64::MAX << (from - block_start));
+            if mask != 0 {
+                return block_start + mask.trailing_zeros() as usize;
+            }
+            from = block_start + BLOCK_SIZE;
+        }
+        self.contents.len()
+    }
+
+    fn load(&mut self, block_start: usize) {
+        self.block_start = block_start;
+        self.masks = match self.contents[block_start..].first_chunk::<BLOCK_SIZE>() {
+            Some(block) => unsafe { self.kernel.masks_unchecked(block) },
+            None => {
+                // the end of the buffer, zero bytes are not delimiters
+                let mut block = [0; BLOCK_SIZE];
+                let rest = &self.contents[block_start..];
+                block[..rest.len()].copy_from_slice(rest);
+                unsafe { self.kernel.masks_unchecked(&block) }
+            }
+        };
+    }
+}
+
+
+#[test]
+fn test_kernels() {
+    let mut block = [0u8; BLOCK_SIZE];
+    let mut seed: u64 = 42;
+    for round in 0..1000 {
+        for byte in block.iter_mut() {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            // mostly delimiters and their neighbours, the bytes the bit tricks get wrong
+            *byte = [b';', b'\n', b';' + 1, b'\n' - 1, 0x80 | b';', 0, 0xff, b'a'][(seed >> 61) as usize];
+        }
+        let expected = BlockMasks {
+            semicolons: (0..BLOCK_SIZE).filter(|&idx| block[idx] == b';').map(|idx| 1 << idx).sum(),
+            newlines: (0..BLOCK_SIZE).filter(|&idx| block[idx] == b'\n').map(|idx| 1 << idx).sum(),
+        };
+        for kernel in Kernel::ALL.into_iter().filter(|kernel| kernel.is_supported()) {
+            assert_eq!(kernel.masks(&block), expected, "{} in round {}", kernel.name(), round);
+        }
+    }
+    assert!(Kernel::detect().is_supported());
+}
+
+#[test]
+fn test_delimiters() {
+    for kernel in Kernel::ALL.into_iter().filter(|kernel| kernel.is_supported()) {
+        let contents = "Hamburg;12.0\nBulawayo;8.9\n".repeat(10);
+        let mut delimiters = Delimiters::with_kernel(contents.as_bytes(), kernel);
+        let mut expected = contents.match_indices([';', '\n']).map(|(idx, _)| idx);
+        let mut from = 0;
+        while from < contents.len() {
+            let next = delimiters.next_delimiter(from);
+            assert_eq!(Some(next), expected.next(), "{}", kernel.name());
+            from = next + 1;
+        }
+        assert_eq!(delimiters.next_delimiter(from), contents.len());
+
+        for (bytes, newline) in [("1111111\n", 7), ("11\n11111", 2), ("0000000011\n11111", 10), ("000000001111111122\n", 18), ("11", 2)] {
+            assert_eq!(Delimiters::with_kernel(bytes.as_bytes(), kernel).next_newline(0), newline);
+        }
+        // a line longer than a block, searched from its middle after a search before it
+        let long = format!("{};1.0\n", "x".repeat(150));
+        let mut delimiters = Delimiters::with_kernel(long.as_bytes(), kernel);
+        assert_eq!(delimiters.next_newline(100), 154);
+        assert_eq!(delimiters.next_delimiter(70), 150);
+        assert_eq!(delimiters.next_newline(155), 155);
+    }
+}
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn prop_delimiters(contents in proptest::collection::vec(prop_oneof![Just(b';'), Just(b'\n'), any::<u8>()], 0..300)) {
+        let scalar = |from: usize, newline_only: bool| contents.iter().skip(from)
+            .position(|&b| b == b'\n' || (b == b';' && !newline_only))
+            .map_or(contents.len(), |idx| from + idx);
+        for kernel in Kernel::ALL.into_iter().filter(|kernel| kernel.is_supported()) {
+            // searches that move forward, as the scanner does, then backward
+            let mut forward = Delimiters::with_kernel(&contents, kernel);
+            let mut backward = Delimiters::with_kernel(&contents, kernel);
+            for from in 0..=contents.len() {
+                prop_assert_eq!(forward.next_delimiter(from), scalar(from, false), "{} from {}", kernel.name(), from);
+                prop_assert_eq!(forward.next_newline(from), scalar(from, true), "{} from {}", kernel.name(), from);
+            }
+            for from in (0..=contents.len()).rev() {
+                prop_assert_eq!(backward.next_newline(from), scalar(from, true), "{} from {}", kernel.name(), from);
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::{BTreeMap, HashSet}, mem, sync::{atomic::{AtomicUsize, Ordering}, Mutex}, thread, time::Instant};
+
+use log::info;
+
+use crate::attempt10::{self, Chunk, MAX_STATIONS};
+use crate::error::Error;
+use crate::hash::{DefaultHash, NameHash};
+use crate::solver::Results;
+use crate::utils;
+use crate::validate::{ErrorPolicy, Rejected, RowError, RowErrorKind, MAX_QUARANTINED_BYTES};
+
+/// Size of the ranges the workers claim, in bytes.
+pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// up to `chunk_size` bytes of one of the inputs, the lines that start in it go to
+// whichever worker claims it
+struct Range {
+    file: usize,
+    from: usize,
+    to: usize,
+}
+
+/// What a worker found in one range of an input. The line number of the error is
+/// relative to the range until the summaries are merged.
+pub(crate) struct RangeSummary {
+    pub index: usize,
+    pub file: usize,
+    pub lines: usize,
+    pub rejected: Rejected,
+    pub error: Option<RowError>,
+    /// The rows that added a station to the table of the worker, see `Chunk::added`.
+    pub added: Vec<(usize, usize, Box<[u8]>)>,
+}
+
+/// The bytes of rejected lines kept for each input by the ranges done so far, in input
+/// order. Once they reach the limit of an input, the later ranges of the input only
+/// count their rejected rows, so that a dump of garbage does not fill the memory with
+/// lines that the merge would drop anyway.
+pub(crate) struct QuarantineBudget {
+    limit: usize,
+    state: Mutex<BudgetState>,
+}
+
+struct BudgetState {
+    // the next range to account for, and the input and bytes of the ranges done after it
+    next: usize,
+    done: BTreeMap<usize, (usize, usize)>,
+    spent: Vec<usize>,
+}
+
+impl QuarantineBudget {
+    pub(crate) fn new(file_count: usize) -> QuarantineBudget {
+        QuarantineBudget::with_limit(file_count, MAX_QUARANTINED_BYTES)
+    }
+
+    fn with_limit(file_count: usize, limit: usize) -> QuarantineBudget {
+        QuarantineBudget { limit, state: Mutex::new(BudgetState { next: 0, done: BTreeMap::new(), spent: vec![0; file_count] }) }
+    }
+
+    /// Whether the ranges before the ones being done already fill the quarantine of
+    /// input `file`.
+    pub(crate) fn is_spent(&self, file: usize) -> bool {
+        self.state.lock().unwrap().spent[file] >= self.limit
+    }
+
+    /// Accounts for the `bytes` of rejected lines range `index` of input `file` kept,
+    /// every range has to be accounted for, even if it was skipped.
+    pub(crate) fn done(&self, index: usize, file: usize, bytes: usize) {
+        let state = &mut *self.state.lock().unwrap();
+        state.done.insert(index, (file, bytes));
+        while let Some((file, bytes)) = state.done.remove(&state.next) {
+            state.spent[file] += bytes;
+            state.next += 1;
+        }
+    }
+}
+
+/// What a worker found in all the ranges it claimed.
+pub struct WorkerResult {
+    /// The table of the worker, reused for all its ranges. It is emptied into `stats`
+    /// whenever the worker moves on to another input, its counters are kept.
+    pub chunk: Chunk,
+    stats: Vec<(usize, Results)>,
+    summaries: Vec<RangeSummary>,
+}
+
+impl WorkerResult {
+    pub(crate) fn new(precision: u32) -> WorkerResult {
+        WorkerResult { chunk: Chunk::new(precision), stats: Vec::new(), summaries: Vec::new() }
+    }
+
+    /// Aggregates the lines that start in `contents[..=bytes_to_process]`, range
+    /// `index` of input `file` whose temperatures have `precision` fractional digits,
+    /// see `attempt10::compute_into`. Returns whether a row stopped the worker.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn compute_range<H: NameHash>(&mut self, index: usize, file: usize, precision: u32, contents: &[u8], ignore_first_line: bool, bytes_to_process: isize, start_offset: usize, policy: ErrorPolicy, budget: &QuarantineBudget) -> bool {
+        let spent = policy == ErrorPolicy::Quarantine && budget.is_spent(file);
+        if self.stats.last().is_none_or(|(last, _)| *last != file) {
+            self.flush();
+            self.stats.push((file, Results::new()));
+            self.chunk.precision = precision;
+        }
+        let chunk = &mut self.chunk;
+        let lines_before = chunk.lines;
+        attempt10::compute_into::<H>(chunk, contents, ignore_first_line, bytes_to_process, start_offset, if spent { ErrorPolicy::Skip } else { policy });
+        let error = chunk.error.take().map(|mut error| {
+            error.line -= lines_before;
+            error
+        });
+        let failed = error.is_some();
+        let added = chunk.added.drain(..).map(|(line, offset, name)| (line - lines_before, offset, name)).collect();
+        let mut rejected = mem::take(&mut chunk.rejected);
+        if spent {
+            rejected.dropped = rejected.total();
+        }
+        budget.done(index, file, rejected.lines.len());
+        self.summaries.push(RangeSummary {
+            index,
+            file,
+            lines: chunk.lines - lines_before,
+            rejected,
+            error,
+            added,
+        });
+        failed
+    }
+
+    // moves the stations of the table to the stats of the input it was filled from
+    pub(crate) fn flush(&mut self) {
+        if let Some((_, stats)) = self.stats.last_mut() {
+            attempt10::merge_table(stats, &self.chunk.table, self.chunk.precision);
+            self.chunk.table.clear();
+        }
+    }
+}
+
+/// Aggregates each of `inputs` with `thread_count` workers. Instead of a fixed share
+/// of the input, each worker claims the next range of `chunk_size` bytes from a
+/// shared cursor whenever it is done with the previous one, so that a slow thread
+/// or a dense region only holds up the ranges it claimed. A range starts after the
+/// first newline at or after its start, unless it is the start of an input, and ends
+/// with the line that runs across its end. The precision of each input is detected
+/// from its start when None. Fails if a worker panicked, the ranges it claimed are
+/// lost, or without any thread.
+pub fn distribute_work<H: NameHash>(inputs: &[&[u8]], thread_count: usize, policy: ErrorPolicy, precision: Option<u32>, chunk_size: usize) -> Result<Vec<WorkerResult>, Error> {
+    if thread_count == 0 {
+        return Err(Error::NoThreads);
+    }
+    // checked even without any input
+    let default_precision = attempt10::resolve_precision(&[], precision)?;
+    let precisions = inputs.iter().map(|contents| attempt10::resolve_precision(contents, precision)).collect::<Result<Vec<_>, _>>()?;
+    let ranges: Vec<Range> = inputs.iter().enumerate().flat_map(|(file, contents)| {
+        (0..contents.len()).step_by(chunk_size).map(move |from| Range { file, from, to: (from + chunk_size).min(contents.len()) })
+    }).collect();
+    let cursor = AtomicUsize::new(0);
+    // index of the first range of each input in which a worker stopped, the ones after
+    // it are skipped
+    let first_errors: Vec<AtomicUsize> = inputs.iter().map(|_| AtomicUsize::new(usize::MAX)).collect();
+    let budget = QuarantineBudget::new(inputs.len());
+
+    let worker = || {
+        let mut result = WorkerResult::new(default_precision);
+        loop {
+            let index = cursor.fetch_add(1, Ordering::Relaxed);
+            let Some(range) = ranges.get(index) else {
+                break;
+            };
+            if index > first_errors[range.file].load(Ordering::Relaxed) {
+                budget.done(index, range.file, 0);
+                continue;
+            }
+            let contents = &inputs[range.file][range.from..];
+            if result.compute_range::<H>(index, range.file, precisions[range.file], contents, range.from != 0, (range.to - range.from) as isize, range.from, policy, &budget) {
+                first_errors[range.file].fetch_min(index, Ordering::Relaxed);
+            }
+        }
+        result.flush();
+        result
+    };
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..thread_count).map(|_| scope.spawn(worker)).collect();
+        handles.into_iter().map(|handle| handle.join().map_err(Error::worker_panic)).collect()
+    })
+}
+
+/// Merges what the workers found into the results of each of the `file_count`
+/// inputs. The ranges are merged in input order, so that the line numbers and the
+/// rejected rows are the same whichever worker got which range. An input with more
+/// than `MAX_STATIONS` distinct stations fails at the row that adds the first one too
+/// many, even if none of the tables got full.
+pub fn merge_workers(workers: Vec<WorkerResult>, file_count: usize) -> Vec<Result<(Results, Rejected), Error>> {
+    let mut results: Vec<_> = (0..file_count).map(|_| Ok((Results::new(), Rejected::default()))).collect();
+    let mut lines = vec![0; file_count];
+    // the distinct stations of each input, a table only holds the ones its worker came
+    // across, and forgets them when the worker moves on to another input
+    let mut stations: Vec<HashSet<Box<[u8]>>> = (0..file_count).map(|_| HashSet::new()).collect();
+    let mut summaries = Vec::new();
+    let mut stats = Vec::new();
+    for worker in workers {
+        summaries.extend(worker.summaries);
+        stats.extend(worker.stats);
+    }
+    summaries.sort_by_key(|summary| summary.index);
+
+    for summary in summaries {
+        let Ok((_, rejected)) = &mut results[summary.file] else {
+            continue;
+        };
+        let mut error = summary.error.map(|error| RowError { line: lines[summary.file] + error.line, ..error });
+        for (line, offset, name) in summary.added {
+            if stations[summary.file].insert(name) && stations[summary.file].len() > MAX_STATIONS {
+                error = Some(RowError { line: lines[summary.file] + line, offset, kind: RowErrorKind::TooManyStations(MAX_STATIONS) });
+                break;
+            }
+        }
+        if let Some(error) = error {
+            results[summary.file] = Err(error.into());
+            continue;
+        }
+        lines[summary.file] += summary.lines;
+        rejected.merge(summary.rejected);
+    }
+    for (file, stats) in stats {
+        if let Ok((result, _)) = &mut results[file] {
+            utils::merge_results(result, stats);
+        }
+    }
+    results
+}
+
+/// `distribute_work` and `merge_workers` with the default hash and chunk size.
+pub fn run(inputs: &[&[u8]], thread_count: usize, policy: ErrorPolicy, precision: Option<u32>) -> Vec<Result<(Results, Rejected), Error>> {
+    let start_time = Instant::now();
+    let workers = match distribute_work::<DefaultHash>(inputs, thread_count, policy, precision, CHUNK_SIZE) {
+        Ok(workers) => workers,
+        Err(Error::InvalidPrecision(precision)) => return inputs.iter().map(|_| Err(Error::InvalidPrecision(precision))).collect(),
+        Err(Error::NoThreads) => return inputs.iter().map(|_| Err(Error::NoThreads)).collect(),
+        // any of the inputs may have lost ranges to the worker
+        Err(error) => {
+            let message = match error {
+                Error::WorkerPanic(message) => message,
+                error => error.to_string(),
+            };
+            return inputs.iter().map(|_| Err(Error::WorkerPanic(message.clone()))).collect();
+        }
+    };
+    info!("Time taken to compute the stats: {} milliseconds", start_time.elapsed().as_millis());
+    merge_workers(workers, inputs.len())
+}
+
+
+#[cfg(test)]
+fn run_with_chunk_size(inputs: &[&[u8]], thread_count: usize, policy: ErrorPolicy, chunk_size: usize) -> Vec<Result<(Results, Rejected), Error>> {
+    merge_workers(distribute_work::<DefaultHash>(inputs, thread_count, policy, None, chunk_size).unwrap(), inputs.len())
+}
+
+#[test]
+fn test_distribute_work() {
+    let contents = std::fs::read("data/test_small.csv").unwrap();
+    let expected = crate::attempt1::naive_btree_kstat("data/test_small.csv");
+    // ranges that end on a newline, right after one, in the middle of a line, or hold
+    // no line start at all
+    for chunk_size in [1, 2, 7, 13, 14, 64, 1000, 1 << 20] {
+        for thread_count in [1, 3, 8] {
+            let (actual, rejected) = run_with_chunk_size(&[&contents], thread_count, ErrorPolicy::Strict, chunk_size).remove(0).unwrap();
+            assert_eq!(actual, expected, "ranges of {} bytes with {} threads", chunk_size, thread_count);
+            assert!(rejected.is_empty());
+        }
+    }
+
+    let workers = distribute_work::<DefaultHash>(&[&contents], 2, ErrorPolicy::Strict, None, 64).unwrap();
+    let lines: usize = workers.iter().map(|worker| worker.chunk.lines).sum();
+    assert_eq!(lines, contents.iter().filter(|&&b| b == b'\n').count());
+    assert!(run(&[], 2, ErrorPolicy::Strict, None).is_empty());
+    assert_eq!(run(&[b""], 2, ErrorPolicy::Strict, None)[0].as_ref().unwrap().0, Results::new());
+}
+
+#[test]
+fn test_distribute_work_errors() {
+    let malformed = b"Hamburg;12.0\nBulawayo;8.9\n\nHamburg;-1.0\nBad;row\nSt. John's;15.2\n";
+    let valid = b"Hamburg;10.0\nPalembang;38.8";
+    for chunk_size in [1, 5, 16, 1024] {
+        for thread_count in [1, 3] {
+            let outcomes = run_with_chunk_size(&[malformed, valid], thread_count, ErrorPolicy::Strict, chunk_size);
+            assert_eq!(outcomes[0].as_ref().unwrap_err().to_string(), "line 3 (byte offset 26): empty line");
+            assert_eq!(outcomes[1].as_ref().unwrap().0["Hamburg"].sum, 100);
+
+            let outcomes = run_with_chunk_size(&[malformed, valid], thread_count, ErrorPolicy::Quarantine, chunk_size);
+            let (result, rejected) = outcomes[0].as_ref().unwrap();
+            assert_eq!((result["Hamburg"].sum, result["Hamburg"].count), (110, 2));
+            assert_eq!(rejected.lines, b"\nBad;row\n");
+        }
+    }
+}
+
+#[test]
+fn test_quarantine_budget() {
+    let budget = QuarantineBudget::with_limit(2, 10);
+    budget.done(1, 0, 20);
+    budget.done(2, 1, 20);
+    assert!(!budget.is_spent(0) && !budget.is_spent(1));
+    budget.done(0, 0, 0);
+    assert!(budget.is_spent(0) && budget.is_spent(1));
+
+    let mut contents = Vec::new();
+    for idx in 0..3 * MAX_QUARANTINED_BYTES / 1000 {
+        contents.extend_from_slice(format!("#{:0998}\n", idx).as_bytes());
+    }
+    let (_, rejected) = run(&[&contents], 2, ErrorPolicy::Quarantine, None).remove(0).unwrap();
+    assert!(rejected.lines == contents[..MAX_QUARANTINED_BYTES / 1000 * 1000]);
+    assert_eq!((rejected.total(), rejected.dropped), ((contents.len() / 1000) as u64, (contents.len() / 1000 - MAX_QUARANTINED_BYTES / 1000) as u64));
+}
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+#[cfg(test)]
+fn row_strategy() -> impl Strategy<Value = Vec<u8>> {
+    ("[a-zA-Z é東]{1,12}", -999..=999i64).prop_map(|(name, tenths)| format!("{};{}\n", name, utils::format_tenths(tenths)).into_bytes())
+}
+
+#[cfg(test)]
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    // well formed rows, with or without the last newline
+    #[test]
+    fn prop_independent_of_split(rows in proptest::collection::vec(row_strategy(), 1..100), trailing_newline in any::<bool>()) {
+        let mut contents = rows.concat();
+        if !trailing_newline {
+            contents.pop();
+        }
+        let (expected, _) = crate::differential::oracle(&contents, 1);
+        for chunk_size in [1, 3, 16, 64, contents.len()] {
+            for thread_count in [1, 3] {
+                let (actual, rejected) = run_with_chunk_size(&[&contents], thread_count, ErrorPolicy::Strict, chunk_size).remove(0).unwrap();
+                prop_assert_eq!(&actual, &expected, "ranges of {} bytes with {} threads", chunk_size, thread_count);
+                prop_assert!(rejected.is_empty());
+            }
+        }
+    }
+
+    // well formed rows mixed with any bytes, mostly the ones the scanner and the parser
+    // look for
+    #[test]
+    fn prop_arbitrary_bytes(pieces in proptest::collection::vec(prop_oneof![
+        row_strategy(),
+        proptest::collection::vec(prop_oneof![
+            Just(b';'), Just(b'\n'), Just(b'.'), Just(b'-'), Just(b'#'), b'0'..=b'9', b'a'..=b'c', any::<u8>()
+        ], 0..8),
+    ], 0..60)) {
+        let contents = pieces.concat();
+        let expected = crate::differential::oracle(&contents, 1);
+        for chunk_size in [1, 3, 16, 64, contents.len().max(1)] {
+            for thread_count in [1, 3] {
+                let workers = distribute_work::<DefaultHash>(&[&contents], thread_count, ErrorPolicy::Skip, Some(1), chunk_size).unwrap();
+                let actual = merge_workers(workers, 1).remove(0).unwrap();
+                prop_assert_eq!(&actual, &expected, "ranges of {} bytes with {} threads", chunk_size, thread_count);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use crate::{attempt1, attempt10, attempt2, attempt3, attempt4, attempt5, attempt6, attempt7, attempt8, attempt9};
+use crate::utils::KeyedStat;
+use crate::error::Error;
+use crate::validate::{ErrorPolicy, Rejected};
+
+/// Per-station results, ordered by station name.
+pub type Results = BTreeMap<String, KeyedStat>;
+
+#[derive(Clone, Debug)]
+pub struct SolverConfig {
+    pub thread_count: usize,
+    pub policy: ErrorPolicy,
+    /// Fractional digits of the temperatures, detected from the input when None. Only
+    /// the solvers whose `supports_precision` is true honour it, the others expect one.
+    pub precision: Option<u32>,
+}
+
+impl SolverConfig {
+    pub fn new(thread_count: usize) -> SolverConfig {
+        SolverConfig {
+            thread_count,
+            policy: ErrorPolicy::default(),
+            precision: None,
+        }
+    }
+}
+
+/// Common interface over the attemptN strategies, so that they can be run, tested
+/// and benchmarked uniformly.
+pub trait Solver: Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn solve(&self, input: &str, config: &SolverConfig) -> Results;
+
+    /// Whether `SolverConfig::precision` is honoured. The solvers that do not support it
+    /// read one fractional digit, and print wrong numbers for more.
+    fn supports_precision(&self) -> bool {
+        false
+    }
+
+    /// Whether malformed rows are handled according to `SolverConfig::policy`. The
+    /// solvers that do not support it expect a well formed input, never reject anything
+    /// and may count a malformed row as a station.
+    fn supports_error_policies(&self) -> bool {
+        false
+    }
+
+    /// Like `solve`, but fails instead of panicking when the input cannot be read or a
+    /// worker panics, and handles malformed rows according to `config.policy` if
+    /// `supports_error_policies` says so.
+    fn try_solve(&self, input: &str, config: &SolverConfig) -> Result<(Results, Rejected), Error>;
+
+    /// Like `try_solve` for each of `inputs`. By default the inputs are solved one
+    /// after the other, attempt10 shares its threads between all of them instead.
+    fn try_solve_files(&self, inputs: &[&str], config: &SolverConfig) -> Vec<Result<(Results, Rejected), Error>> {
+        inputs.iter().map(|input| self.try_solve(input, config)).collect()
+    }
+}
+
+pub static SOLVERS: [&dyn Solver; 10] = [
+    &attempt1::Attempt1,
+    &attempt2::Attempt2,
+    &attempt3::Attempt3,
+    &attempt4::Attempt4,
+    &attempt5::Attempt5,
+    &attempt6::Attempt6,
+    &attempt7::Attempt7,
+    &attempt8::Attempt8,
+    &attempt9::Attempt9,
+    &attempt10::Attempt10,
+];
+
+pub fn find(name: &str) -> Option<&'static dyn Solver> {
+    SOLVERS.iter().copied().find(|solver| solver.name() == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    SOLVERS.iter().map(|solver| solver.name()).collect()
+}
+
+
+#[test]
+fn test_registry() {
+    let names = names();
+    for (idx, name) in names.iter().enumerate() {
+        assert_eq!(*name, format!("attempt{}", idx + 1));
+        assert_eq!(find(name).unwrap().name(), *name);
+        assert!(!find(name).unwrap().description().is_empty());
+        assert_eq!(find(name).unwrap().supports_precision(), *name == "attempt10");
+        assert_eq!(find(name).unwrap().supports_error_policies(), *name == "attempt10");
+    }
+    assert!(find("attempt11").is_none());
+}
+
+#[test]
+fn test_try_solve_errors() {
+    let config = SolverConfig::new(2);
+    for solver in SOLVERS {
+        let error = solver.try_solve("data/no-such-file.txt", &config).unwrap_err();
+        assert!(matches!(&error, Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound), "{}: {}", solver.name(), error);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{io::{self, Read}, mem, sync::{atomic::{AtomicUsize, Ordering}, mpsc, Arc, Mutex}, thread, time::Instant};
+
+use log::info;
+
+use crate::attempt10;
+use crate::error::Error;
+use crate::hash::DefaultHash;
+use crate::schedule::{self, QuarantineBudget, WorkerResult};
+use crate::solver::Results;
+use crate::validate::{ErrorPolicy, Rejected};
+
+/// Size of the blocks handed to the workers, in bytes.
+pub const BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+// complete lines starting at `offset` in the input, only the last block may end
+// without a newline
+struct Block {
+    index: usize,
+    offset: usize,
+    data: Vec<u8>,
+}
+
+// Reads about `block_size` bytes and cuts them after the last newline, the partial
+// line is kept in `carry` for the next block. Returns an empty block at the end of
+// the input.
+fn read_block<R: Read>(reader: &mut R, carry: &mut Vec<u8>, block_size: usize) -> io::Result<Vec<u8>> {
+    let mut data = mem::take(carry);
+    data.reserve(block_size);
+    // the carried partial line has no newline, only the bytes read are searched
+    let mut searched = data.len();
+    loop {
+        let read = reader.by_ref().take(block_size as u64).read_to_end(&mut data)?;
+        if read == 0 {
+            return Ok(data);
+        }
+        if let Some(last_newline) = data[searched..].iter().rposition(|&b| b == b'\n') {
+            let end = searched + last_newline + 1;
+            carry.extend_from_slice(&data[end..]);
+            data.truncate(end);
+            return Ok(data);
+        }
+        // the line is longer than a block, keep reading
+        searched = data.len();
+    }
+}
+
+fn worker(receiver: &Mutex<mpsc::Receiver<Block>>, first_error: &AtomicUsize, budget: &QuarantineBudget, policy: ErrorPolicy, precision: u32) -> WorkerResult {
+    let mut result = WorkerResult::new(precision);
+    loop {
+        let block = match receiver.lock().unwrap().recv() {
+            Ok(block) => block,
+            Err(_) => break,
+        };
+        // the blocks before a malformed row are still needed for its line number
+        if block.index > first_error.load(Ordering::Relaxed) {
+            budget.done(block.index, 0, 0);
+            continue;
+        }
+
+        if result.compute_range::<DefaultHash>(block.index, 0, precision, &block.data, false, block.data.len() as isize - 1, block.offset, policy, budget) {
+            first_error.fetch_min(block.index, Ordering::Relaxed);
+        }
+    }
+    result.flush();
+    result
+}
+
+/// Aggregates measurements read from `reader`, e.g. stdin or a pipe, without
+/// buffering the whole input: the input is read in blocks of complete lines that
+/// are handed to `thread_count` workers, each filling its own table. A malformed
+/// row under `ErrorPolicy::Strict` fails with `Error::Parse`, no thread with
+/// `Error::NoThreads`.
+pub fn aggregate<R: Read>(reader: R, thread_count: usize, policy: ErrorPolicy, precision: Option<u32>) -> Result<(Results, Rejected), Error> {
+    aggregate_blocks(reader, thread_count, policy, precision, BLOCK_SIZE)
+}
+
+fn aggregate_blocks<R: Read>(mut reader: R, thread_count: usize, policy: ErrorPolicy, precision: Option<u32>, block_size: usize) -> Result<(Results, Rejected), Error> {
+    // without a worker the blocks would be dropped unread
+    if thread_count == 0 {
+        return Err(Error::NoThreads);
+    }
+    let start_time = Instant::now();
+    let mut carry = Vec::new();
+    let first_block = read_block(&mut reader, &mut carry, block_size)?;
+    let precision = attempt10::resolve_precision(&first_block, precision)?;
+
+    // bounded, so that a slow worker pool does not buffer the whole input
+    let (sender, receiver) = mpsc::sync_channel::<Block>(thread_count);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let first_error = &AtomicUsize::new(usize::MAX);
+    let budget = &QuarantineBudget::new(1);
+
+    let (read_result, workers) = thread::scope(|scope| {
+        // the workers own the receiver, once they are all gone, even by panicking, the
+        // reader stops instead of waiting for them forever
+        let handles: Vec<_> = (0..thread_count).map(|_| {
+            let receiver = receiver.clone();
+            scope.spawn(move || worker(&receiver, first_error, budget, policy, precision))
+        }).collect();
+        drop(receiver);
+
+        let read_blocks = || -> io::Result<()> {
+            let (mut block, mut index, mut offset) = (first_block, 0, 0);
+            while !block.is_empty() && first_error.load(Ordering::Relaxed) == usize::MAX {
+                let len = block.len();
+                if sender.send(Block { index, offset, data: block }).is_err() {
+                    break;
+                }
+                index += 1;
+                offset += len;
+                block = read_block(&mut reader, &mut carry, block_size)?;
+            }
+            Ok(())
+        };
+        let read_result = read_blocks();
+        drop(sender);
+        let workers: Result<Vec<_>, _> = handles.into_iter().map(|handle| handle.join().map_err(Error::worker_panic)).collect();
+        (read_result, workers)
+    });
+    read_result?;
+
+    let outcome = schedule::merge_workers(workers?, 1).remove(0);
+    info!("Time taken to aggregate the stream: {} milliseconds", start_time.elapsed().as_millis());
+    outcome
+}
+
+
+#[test]
+fn test_aggregate_blocks() {
+    let path = "data/test_small.csv";
+    let contents = std::fs::read(path).unwrap();
+    let expected = crate::aggregate_file(path, 1).unwrap();
+    for block_size in [1, 7, 64, 1 << 20] {
+        for thread_count in [1, 3] {
+            let (actual, rejected) = aggregate_blocks(contents.as_slice(), thread_count, ErrorPolicy::Strict, None, block_size).unwrap();
+            assert_eq!(actual, expected, "blocks of {} bytes with {} threads", block_size, thread_count);
+            assert!(rejected.is_empty());
+        }
+    }
+
+    let (actual, _) = aggregate_blocks(&b"Hamburg;12.0\nHamburg;-3.5"[..], 2, ErrorPolicy::Strict, None, 8).unwrap();
+    assert_eq!((actual["Hamburg"].sum, actual["Hamburg"].count), (85, 2));
+    assert!(aggregate_blocks(io::empty(), 2, ErrorPolicy::Strict, None, 8).unwrap().0.is_empty());
+
+    let (mut carry, mut reader) = (Vec::new(), &b"Hamburg;12.0\nBulawayo;8.9\nx"[..]);
+    assert_eq!(read_block(&mut reader, &mut carry, 4).unwrap(), b"Hamburg;12.0\n");
+    assert_eq!(read_block(&mut reader, &mut carry, 4).unwrap(), b"Bulawayo;8.9\n");
+    assert_eq!(read_block(&mut reader, &mut carry, 4).unwrap(), b"x");
+    assert!(read_block(&mut reader, &mut carry, 4).unwrap().is_empty());
+}
+
+#[test]
+fn test_aggregate_blocks_errors() {
+    let contents = b"# header\nHamburg;12.0\n\nBulawayo;8.9\nNo semicolon\nHamburg;-1.0\nBad;row\nSt. John's;15.2\n";
+    for block_size in [1, 16, 1024] {
+        let (actual, rejected) = aggregate_blocks(&contents[..], 3, ErrorPolicy::Quarantine, None, block_size).unwrap();
+        assert_eq!(actual.len(), 3);
+        assert_eq!(rejected.lines, b"# header\n\nNo semicolon\nBad;row\n");
+
+        let mut contents = contents.to_vec();
+        contents.drain(..9);
+        let error = aggregate_blocks(contents.as_slice(), 3, ErrorPolicy::Strict, None, block_size).unwrap_err();
+        assert!(matches!(error, Error::Parse(_)));
+        assert_eq!(error.to_string(), "line 2 (byte offset 13): empty line");
+    }
+
+    struct FailingReader;
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken pipe"))
+        }
+    }
+    let error = aggregate_blocks((&b"Hamburg;12.0\nBulawayo;8.9\n"[..]).chain(FailingReader), 2, ErrorPolicy::Strict, None, 16).unwrap_err();
+    assert!(matches!(&error, Error::Io(e) if e.to_string() == "broken pipe"));
+}
@@ -1,9 +1,6 @@
-use core::fmt;
-use core::hash::Hash;
 use std::collections::BTreeMap;
+use std::io::{self, Write};
 use std::{collections::HashMap, time::Instant};
-use std::hash::BuildHasherDefault;
-// use gxhash;
 
 use log::info;
 
@@ -13,94 +10,286 @@ pub const THREAD_COUNT: usize = 16;
 pub const MAX_LINE_SIZE: usize = 107; //<100_BYTE_NAME><1_BYTE_SEMICOLON><6_BYTE_TEMPRATURE>, temprature is atmost 6 bytes since -99.9 >= temp <= 99.9   
 
 
+/// Temperatures are kept in fixed point, as integers with `precision` implied decimals
+/// (tenths of a degree for the standard dataset), so that sums stay exact over billions
+/// of rows. Use `mean()` and `format_fixed()` when printing.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct KeyedStat {
     pub station:  [u8; 100],
-    pub min: f32,
-    pub max: f32,
-    pub sum: f32,
-    pub count: f32,
-    pub len: usize
+    pub min: i32,
+    pub max: i32,
+    pub sum: i64,
+    pub count: u64,
+    pub len: usize,
+    pub precision: u32,
+}
+
+impl Default for KeyedStat {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl KeyedStat {
     pub fn new() -> KeyedStat {
         KeyedStat {
             station: [0; 100], 
-            min: 0.0, 
-            max: 0.0,
-            sum: 0.0,
-            count: 0.0,
+            min: 0, 
+            max: 0,
+            sum: 0,
+            count: 0,
             len: 0,
+            precision: 1,
+        }
+    }
+
+    pub fn from_stat(name: &str, stat: &Stat) -> KeyedStat {
+        let mut ks = KeyedStat {
+            min: stat.min,
+            max: stat.max,
+            sum: stat.sum,
+            count: stat.count,
+            ..KeyedStat::new()
+        };
+        ks.len = name.len();
+        ks.station[0..name.len()].copy_from_slice(name.as_bytes());
+        ks
+    }
+
+    /// Mean temperature in degrees.
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64 / scale(self.precision) as f64
+    }
+
+    /// Mean temperature in the same fixed point unit as `min` and `max`, rounded as the
+    /// challenge expects.
+    pub fn mean_scaled(&self) -> i64 {
+        rounded_mean(self.sum, self.count)
+    }
+
+    /// The same measurements with `precision` decimals, which must not be less than
+    /// the current ones.
+    pub fn rescaled(&self, precision: u32) -> KeyedStat {
+        let factor = scale(precision - self.precision);
+        KeyedStat {
+            min: self.min * factor as i32,
+            max: self.max * factor as i32,
+            sum: self.sum * factor,
+            precision,
+            ..*self
         }
     }
+
+    /// Adds the measurements of `other`, e.g. the same station in another file. The
+    /// result has the finer of the two precisions.
+    pub fn merge(&mut self, other: &KeyedStat) {
+        let precision = self.precision.max(other.precision);
+        let other = other.rescaled(precision);
+        *self = self.rescaled(precision);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
 }
 
+/// Same fixed point representation as `KeyedStat`.
 pub struct Stat {
-    pub min: f32, 
-    pub max: f32, 
-    pub sum: f32, 
-    pub count: f32
+    pub min: i32, 
+    pub max: i32, 
+    pub sum: i64, 
+    pub count: u64
+}
+
+impl Stat {
+    /// Mean temperature in degrees.
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64 / 10.0
+    }
+
+    /// Mean temperature in tenths of a degree, rounded as the challenge expects.
+    pub fn mean_tenths(&self) -> i64 {
+        rounded_mean(self.sum, self.count)
+    }
+}
+
+/// Value of one degree in a fixed point unit with `precision` decimals.
+pub fn scale(precision: u32) -> i64 {
+    10i64.pow(precision)
+}
+
+/// Converts a temperature in tenths of a degree to degrees.
+pub fn to_degrees(tenths: i32) -> f64 {
+    f64::from(tenths) / 10.0
+}
+
+/// Parses a temperature such as "-12.3" into tenths of a degree, rounding any extra
+/// fractional digits to the nearest tenth.
+pub fn parse_tenths(temp: &str) -> i32 {
+    let value: f64 = temp.parse().unwrap();
+    (value * 10.0).round() as i32
+}
+
+/// Adds the stations of `other` to `results`. When they were computed with different
+/// precisions, all the stations end up with the finest one.
+pub fn merge_results(results: &mut BTreeMap<String, KeyedStat>, other: BTreeMap<String, KeyedStat>) {
+    let precision = results.values().chain(other.values()).map(|stat| stat.precision).max();
+    for (station, stat) in other {
+        results.entry(station).and_modify(|existing| existing.merge(&stat)).or_insert(stat);
+    }
+    if let Some(precision) = precision {
+        for stat in results.values_mut().filter(|stat| stat.precision != precision) {
+            *stat = stat.rescaled(precision);
+        }
+    }
+}
+
+pub fn to_btreemap_kstat<I: IntoIterator<Item = (String, Stat)>>(table: I) -> BTreeMap<String, KeyedStat> {
+    table.into_iter().map(|(key, stat)| {
+        let ks = KeyedStat::from_stat(&key, &stat);
+        (key, ks)
+    }).collect()
+}
+
+/// Formats a temperature in tenths of a degree with exactly one decimal, e.g. -5 => "-0.5".
+pub fn format_tenths(tenths: i64) -> String {
+    format_fixed(tenths, 1)
 }
 
-pub fn print_result_hashmap<S>(table: &HashMap<String, Stat, S>) where S:  std::hash::BuildHasher  {
+/// Formats a fixed point temperature with exactly `precision` decimals, e.g.
+/// (-61750, 4) => "-6.1750".
+pub fn format_fixed(value: i64, precision: u32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let abs = value.unsigned_abs();
+    let scale = scale(precision) as u64;
+    if precision == 0 {
+        return format!("{}{}", sign, abs);
+    }
+    format!("{}{}.{:0width$}", sign, abs / scale, abs % scale, width = precision as usize)
+}
+
+/// Mean of fixed point values, rounded to the unit half towards positive infinity like
+/// the reference implementation does (Math.round). Computed on integers, so that no
+/// binary float rounding is involved.
+pub fn rounded_mean(sum: i64, count: u64) -> i64 {
+    let count = count as i128;
+    (2 * sum as i128 + count).div_euclid(2 * count) as i64
+}
+
+// {<station>=<min>/<mean>/<max>, ...}, the format of the official challenge
+fn write_results<'a, W, I>(out: &mut W, entries: I) -> io::Result<()> where W: Write + ?Sized, I: Iterator<Item = (&'a str, i32, i64, i32, u32)> {
+    write!(out, "{{")?;
+    for (idx, (key, min, mean, max, precision)) in entries.enumerate() {
+        if idx > 0 {
+            write!(out, ", ")?;
+        }
+        write!(out, "{}={}/{}/{}", key, format_fixed(min.into(), precision), format_fixed(mean, precision), format_fixed(max.into(), precision))?;
+    }
+    writeln!(out, "}}")
+}
+
+pub fn print_result_hashmap<S, W>(table: &HashMap<String, Stat, S>, out: &mut W) -> io::Result<()> where S: std::hash::BuildHasher, W: Write + ?Sized {
     let t1: Instant = Instant::now();
-    let mut idx = 0;
-    let size = table.len();
 
     let mut keys: Vec<_> = table.keys().collect();
     keys.sort();
-    print!("{{");
-    for key in keys {
+    write_results(out, keys.into_iter().map(|key| {
         let stat = table.get(key).unwrap();
-        if idx == size-1 {
-            print!("{}:{}/{}/{:.1}", key, stat.min, stat.max, stat.sum/stat.count);
-        } else {
-            print!("{}:{}/{}/{:.1},", key, stat.min, stat.max, stat.sum/stat.count);
-        }
-        idx+=1;
-    }
-    print!("}}\n");
+        (key.as_str(), stat.min, stat.mean_tenths(), stat.max, 1)
+    }))?;
     let t2: Instant = Instant::now();
     info!("Time taken to print the results: {} milliseconds", (t2-t1).as_millis());
+    Ok(())
 }
 
-pub fn print_result_btreemap_stat(table: &BTreeMap<String, Stat>)  {
+pub fn print_result_btreemap_stat<W: Write + ?Sized>(table: &BTreeMap<String, Stat>, out: &mut W) -> io::Result<()> {
     let start: Instant = Instant::now();
-    let mut idx = 0;
-    let size = table.len();
-
-    print!("{{");
-    for (key, value) in table {
-        let stat = table.get(key).unwrap();
-        if idx == size-1 {
-            print!("{}:{}/{}/{:.1}", *key, stat.min, stat.max, stat.sum/stat.count);
-        } else {
-            print!("{}:{}/{}/{:.1},", *key, stat.min, stat.max, stat.sum/stat.count);
-        }
-        idx+=1;
-    }
-    print!("}}\n");
+    write_results(out, table.iter().map(|(key, stat)| (key.as_str(), stat.min, stat.mean_tenths(), stat.max, 1)))?;
     let end: Instant = Instant::now();
     info!("Time taken to print the results: {} milliseconds", (end-start).as_millis());
+    Ok(())
 }
 
-pub fn print_result_btreemap_kstat(table: &BTreeMap<String, KeyedStat>)  {
+pub fn print_result_btreemap_kstat<W: Write + ?Sized>(table: &BTreeMap<String, KeyedStat>, out: &mut W) -> io::Result<()> {
     let t1: Instant = Instant::now();
-    let mut idx = 0;
-    let size = table.len();
+    write_results(out, table.iter().map(|(key, stat)| (key.as_str(), stat.min, stat.mean_scaled(), stat.max, stat.precision)))?;
+    let t2: Instant = Instant::now();
+    info!("Time taken to print the results: {} milliseconds", (t2-t1).as_millis());
+    Ok(())
+}
 
-    print!("{{");
-    for (key, value) in table {
-        let stat = table.get(key).unwrap();
-        if idx == size-1 {
-            print!("{}:{}/{}/{:.1}", *key, stat.min, stat.max, stat.sum/stat.count);
-        } else {
-            print!("{}:{}/{}/{:.1},", *key, stat.min, stat.max, stat.sum/stat.count);
+
+#[test]
+fn test_parse_tenths() {
+    assert_eq!(parse_tenths("12.3"), 123);
+    assert_eq!(parse_tenths("-0.1"), -1);
+    assert_eq!(parse_tenths("-99.9"), -999);
+    assert_eq!(parse_tenths("5"), 50);
+    assert_eq!(parse_tenths("35.6897"), 357);
+    assert_eq!(to_degrees(-999), -99.9);
+}
+
+#[test]
+fn test_format_tenths() {
+    assert_eq!(format_tenths(0), "0.0");
+    assert_eq!(format_tenths(120), "12.0");
+    assert_eq!(format_tenths(-5), "-0.5");
+    assert_eq!(format_tenths(-999), "-99.9");
+    assert_eq!(format_tenths(999), "99.9");
+    assert_eq!(format_fixed(-61750, 4), "-6.1750");
+    assert_eq!(format_fixed(356897, 4), "35.6897");
+    assert_eq!(format_fixed(-5, 3), "-0.005");
+    assert_eq!(format_fixed(12, 0), "12");
+}
+
+#[test]
+fn test_mean_tenths() {
+    // exact halves round towards positive infinity
+    assert_eq!(rounded_mean(3, 2), 2);
+    assert_eq!(rounded_mean(-3, 2), -1);
+    assert_eq!(rounded_mean(-1, 2), 0);
+    assert_eq!(rounded_mean(437, 3), 146);
+    assert_eq!(rounded_mean(-181, 2), -90);
+    assert_eq!(rounded_mean(999 * 1_000_000_000, 1_000_000_000), 999);
+}
+
+#[test]
+fn test_merge_results() {
+    let mut results = to_btreemap_kstat([
+        (String::from("Hamburg"), Stat { min: -35, max: 120, sum: 85, count: 2 }),
+        (String::from("Bulawayo"), Stat { min: 89, max: 89, sum: 89, count: 1 }),
+    ]);
+    let mut other = to_btreemap_kstat([(String::from("Hamburg"), Stat { min: 1205, max: 1205, sum: 1205, count: 1 })]);
+    other.get_mut("Hamburg").unwrap().precision = 2;
+    merge_results(&mut results, other);
+
+    let hamburg = &results["Hamburg"];
+    assert_eq!((hamburg.min, hamburg.max, hamburg.sum, hamburg.count, hamburg.precision), (-350, 1205, 2055, 3, 2));
+    let bulawayo = &results["Bulawayo"];
+    assert_eq!((bulawayo.min, bulawayo.sum, bulawayo.precision), (890, 890, 2));
+}
+
+#[test]
+fn test_reference_outputs() {
+    let mut samples: Vec<_> = std::fs::read_dir("data/samples").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    samples.sort();
+    assert!(!samples.is_empty());
+
+    for sample in samples {
+        let input = sample.to_str().unwrap();
+        let expected = std::fs::read_to_string(sample.with_extension("out")).unwrap();
+
+        let mut actual = Vec::new();
+        print_result_btreemap_kstat(&crate::attempt1::naive_btree_kstat(input), &mut actual).unwrap();
+        assert_eq!(String::from_utf8(actual).unwrap(), expected, "attempt1 on {}", input);
+
+        for thread_count in [1, 3] {
+            let mut actual = Vec::new();
+            print_result_btreemap_kstat(&crate::aggregate_file(input, thread_count).unwrap(), &mut actual).unwrap();
+            assert_eq!(String::from_utf8(actual).unwrap(), expected, "attempt10 with {} threads on {}", thread_count, input);
         }
-        idx+=1;
     }
-    print!("}}\n");
-    let t2: Instant = Instant::now();
-    info!("Time taken to print the results: {} milliseconds", (t2-t1).as_millis());
-}
\ No newline at end of file
+}
@@ -0,0 +1,379 @@
+use std::{collections::BTreeMap, fmt, fs::File, io::{self, Write}, str, time::Instant};
+
+use log::info;
+use memmap2::Mmap;
+
+/// Longest station name the fast parsers can hold, in bytes.
+pub const MAX_NAME_LEN: usize = 100;
+/// Fractional digits of the temperatures in the standard dataset.
+pub const DEFAULT_PRECISION: u32 = 1;
+/// Keeps 99.999999 within an i32, and the sum of a billion of them within an i64.
+pub const MAX_PRECISION: u32 = 6;
+/// How much of the input `detect_precision` looks at, in bytes.
+pub const PRECISION_SAMPLE_SIZE: usize = 64 * 1024;
+/// How many errors a report keeps, the remaining ones are only counted.
+pub const MAX_REPORTED_ERRORS: usize = 1000;
+/// How many bytes of rejected lines are kept for the quarantine file of an input, the
+/// rows rejected after them are only counted.
+pub const MAX_QUARANTINED_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RowErrorKind {
+    EmptyLine,
+    Comment,
+    MissingSemicolon,
+    EmptyName,
+    NameTooLong(usize),
+    InvalidUtf8,
+    InvalidTemperature,
+    UnsupportedPrecision { digits: usize, precision: u32 },
+    TemperatureOutOfRange,
+    /// The row is well formed but adds a station beyond the limit of the hash table
+    /// (`attempt10::MAX_STATIONS`), which stops the aggregation whatever the policy.
+    TooManyStations(usize),
+}
+
+impl RowErrorKind {
+    /// Short description of the kind, without the details, used to group rejected rows.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RowErrorKind::EmptyLine => "empty line",
+            RowErrorKind::Comment => "comment",
+            RowErrorKind::MissingSemicolon => "missing semicolon",
+            RowErrorKind::EmptyName => "empty name",
+            RowErrorKind::NameTooLong(_) => "name too long",
+            RowErrorKind::InvalidUtf8 => "invalid UTF-8",
+            RowErrorKind::InvalidTemperature => "invalid temperature",
+            RowErrorKind::UnsupportedPrecision { .. } => "unsupported precision",
+            RowErrorKind::TemperatureOutOfRange => "temperature out of range",
+            RowErrorKind::TooManyStations(_) => "too many stations",
+        }
+    }
+}
+
+impl fmt::Display for RowErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowErrorKind::EmptyLine => write!(f, "empty line"),
+            RowErrorKind::Comment => write!(f, "comment line"),
+            RowErrorKind::MissingSemicolon => write!(f, "missing ';' between the station name and the temperature"),
+            RowErrorKind::EmptyName => write!(f, "empty station name"),
+            RowErrorKind::NameTooLong(len) => write!(f, "station name is {} bytes long, the maximum is {}", len, MAX_NAME_LEN),
+            RowErrorKind::InvalidUtf8 => write!(f, "station name is not valid UTF-8"),
+            RowErrorKind::InvalidTemperature => write!(f, "temperature is not a number"),
+            RowErrorKind::UnsupportedPrecision { digits, precision: 1 } => write!(f, "temperature has {} fractional digits, expected 1", digits),
+            RowErrorKind::UnsupportedPrecision { digits, precision } => write!(f, "temperature has {} fractional digits, expected 1 to {}", digits, precision),
+            RowErrorKind::TemperatureOutOfRange => write!(f, "temperature is outside [-99.9, 99.9]"),
+            RowErrorKind::TooManyStations(max) => write!(f, "more than {} distinct stations", max),
+        }
+    }
+}
+
+/// A malformed row. `line` is 1-based and `offset` is the byte offset of the start of
+/// the line in the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowError {
+    pub line: usize,
+    pub offset: usize,
+    pub kind: RowErrorKind,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} (byte offset {}): {}", self.line, self.offset, self.kind)
+    }
+}
+
+impl std::error::Error for RowError {}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub error_count: usize,
+    /// The first `MAX_REPORTED_ERRORS` errors.
+    pub errors: Vec<RowError>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.error_count == 0
+    }
+}
+
+
+/// What the aggregation does with a row that does not parse.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorPolicy {
+    /// Stop at the first malformed row and report it.
+    #[default]
+    Strict,
+    /// Ignore malformed rows, only counting them.
+    Skip,
+    /// Like `Skip`, but also keep the rejected lines so that they can be written to a
+    /// side file.
+    Quarantine,
+}
+
+/// Rows ignored under `ErrorPolicy::Skip` and `ErrorPolicy::Quarantine`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rejected {
+    /// Number of rejected rows per `RowErrorKind::reason`.
+    pub counts: BTreeMap<&'static str, u64>,
+    /// The rejected lines in input order, each terminated by a newline. Only kept
+    /// with `ErrorPolicy::Quarantine`, up to `MAX_QUARANTINED_BYTES`.
+    pub lines: Vec<u8>,
+    /// Rows rejected with `ErrorPolicy::Quarantine` after `lines` was full, whose
+    /// lines were not kept.
+    pub dropped: u64,
+}
+
+impl Rejected {
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn add(&mut self, kind: &RowErrorKind, line: &[u8], policy: ErrorPolicy) {
+        *self.counts.entry(kind.reason()).or_insert(0) += 1;
+        if policy == ErrorPolicy::Quarantine {
+            if self.dropped == 0 && self.lines.len() + line.len() < MAX_QUARANTINED_BYTES {
+                self.lines.extend_from_slice(line);
+                self.lines.push(b'\n');
+            } else {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Appends the rows rejected in a later part of the input.
+    pub fn merge(&mut self, other: Rejected) {
+        self.merge_within(other, MAX_QUARANTINED_BYTES);
+    }
+
+    // keeps the lines of `other` while they fit in `limit` bytes, so that the kept
+    // lines are always the first rejected ones
+    fn merge_within(&mut self, other: Rejected, limit: usize) {
+        for (reason, count) in other.counts {
+            *self.counts.entry(reason).or_insert(0) += count;
+        }
+        let room = if self.dropped == 0 { limit.saturating_sub(self.lines.len()) } else { 0 };
+        let kept = if other.lines.len() <= room {
+            other.lines.len()
+        } else {
+            other.lines[..room].iter().rposition(|&b| b == b'\n').map_or(0, |last| last + 1)
+        };
+        let newlines = |lines: &[u8]| lines.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.dropped += other.dropped + newlines(&other.lines[kept..]);
+        self.lines.extend_from_slice(&other.lines[..kept]);
+    }
+
+    pub fn write_lines(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.lines)
+    }
+}
+
+// e.g. "3 rows rejected (2 comment, 1 empty line)"
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rows rejected", self.total())?;
+        for (idx, (reason, count)) in self.counts.iter().enumerate() {
+            write!(f, "{}{} {}", if idx == 0 { " (" } else { ", " }, count, reason)?;
+        }
+        if !self.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Parses a temperature with 1 to `precision` fractional digits into a fixed point
+/// value with `precision` implied decimals, e.g. ("-6.175", 4) => -61750.
+pub fn parse_temperature(temp: &[u8], precision: u32) -> Result<i32, RowErrorKind> {
+    let (negative, digits) = match temp.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, temp),
+    };
+    let (int_part, fraction_part) = match digits.iter().position(|&b| b == b'.') {
+        Some(idx) => (&digits[..idx], &digits[idx+1..]),
+        None => (digits, &digits[digits.len()..]),
+    };
+    if int_part.is_empty() || !int_part.iter().chain(fraction_part).all(u8::is_ascii_digit) {
+        return Err(RowErrorKind::InvalidTemperature);
+    }
+    if fraction_part.is_empty() || fraction_part.len() > precision as usize {
+        return Err(RowErrorKind::UnsupportedPrecision { digits: fraction_part.len(), precision });
+    }
+    if int_part.len() > 2 {
+        return Err(RowErrorKind::TemperatureOutOfRange);
+    }
+
+    let value = int_part.iter().chain(fraction_part).fold(0, |acc, &b| acc * 10 + i32::from(b - b'0'));
+    let value = value * 10i32.pow(precision - fraction_part.len() as u32);
+    Ok(if negative { -value } else { value })
+}
+
+/// Checks a single line, without its trailing newline, and returns the station name
+/// and the temperature, with `precision` implied decimals.
+pub fn validate_row(line: &[u8], precision: u32) -> Result<(&str, i32), RowErrorKind> {
+    if line.is_empty() {
+        return Err(RowErrorKind::EmptyLine);
+    }
+    if line[0] == b'#' {
+        return Err(RowErrorKind::Comment);
+    }
+    let semicolon = line.iter().position(|&b| b == b';').ok_or(RowErrorKind::MissingSemicolon)?;
+    let (name, temp) = (&line[..semicolon], &line[semicolon+1..]);
+    if name.is_empty() {
+        return Err(RowErrorKind::EmptyName);
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(RowErrorKind::NameTooLong(name.len()));
+    }
+    let name = str::from_utf8(name).map_err(|_| RowErrorKind::InvalidUtf8)?;
+    let temp = parse_temperature(temp, precision)?;
+    Ok((name, temp))
+}
+
+/// Guesses the number of fractional digits of the temperatures from the well formed
+/// rows at the start of `contents`, `DEFAULT_PRECISION` if there are none.
+pub fn detect_precision(contents: &[u8]) -> u32 {
+    let sample = &contents[..contents.len().min(PRECISION_SAMPLE_SIZE)];
+    sample.split(|&b| b == b'\n')
+        .filter(|line| validate_row(line, MAX_PRECISION).is_ok())
+        .map(|line| line.iter().rev().position(|&b| b == b'.').unwrap() as u32)
+        .max()
+        .unwrap_or(DEFAULT_PRECISION)
+}
+
+/// Scans `contents` line by line and reports every malformed row. The last line may
+/// end without a newline, as the aggregators accept it. The precision is detected
+/// from the input when it is None.
+pub fn validate_bytes(contents: &[u8], precision: Option<u32>) -> ValidationReport {
+    let precision = precision.unwrap_or_else(|| detect_precision(contents));
+    let mut report = ValidationReport::default();
+    let mut offset = 0;
+    let mut line_number = 0;
+    while offset < contents.len() {
+        line_number += 1;
+        let (line, next_offset) = match contents[offset..].iter().position(|&b| b == b'\n') {
+            Some(len) => (&contents[offset..offset+len], offset + len + 1),
+            None => (&contents[offset..], contents.len()),
+        };
+
+        report.rows += 1;
+        if let Err(kind) = validate_row(line, precision) {
+            report.error_count += 1;
+            if report.errors.len() < MAX_REPORTED_ERRORS {
+                report.errors.push(RowError { line: line_number, offset, kind });
+            }
+        }
+        offset = next_offset;
+    }
+    report
+}
+
+/// Validates the measurements file at `path`.
+pub fn validate_file(path: &str, precision: Option<u32>) -> io::Result<ValidationReport> {
+    let start_time = Instant::now();
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(ValidationReport::default());
+    }
+    let contents = unsafe { Mmap::map(&file)? };
+    let report = validate_bytes(&contents, precision);
+    info!("Time taken to validate {}: {} milliseconds", path, start_time.elapsed().as_millis());
+    Ok(report)
+}
+
+
+#[test]
+fn test_parse_temperature() {
+    assert_eq!(parse_temperature(b"12.3", 1), Ok(123));
+    assert_eq!(parse_temperature(b"-0.1", 1), Ok(-1));
+    assert_eq!(parse_temperature(b"-99.9", 1), Ok(-999));
+    assert_eq!(parse_temperature(b"100.0", 1), Err(RowErrorKind::TemperatureOutOfRange));
+    assert_eq!(parse_temperature(b"35.6897", 1), Err(RowErrorKind::UnsupportedPrecision { digits: 4, precision: 1 }));
+    assert_eq!(parse_temperature(b"12", 1), Err(RowErrorKind::UnsupportedPrecision { digits: 0, precision: 1 }));
+    assert_eq!(parse_temperature(b"", 1), Err(RowErrorKind::InvalidTemperature));
+    assert_eq!(parse_temperature(b"-.5", 1), Err(RowErrorKind::InvalidTemperature));
+    assert_eq!(parse_temperature(b"1a.5", 1), Err(RowErrorKind::InvalidTemperature));
+    assert_eq!(parse_temperature(b"--1.5", 1), Err(RowErrorKind::InvalidTemperature));
+
+    assert_eq!(parse_temperature(b"35.6897", 4), Ok(356897));
+    assert_eq!(parse_temperature(b"-6.175", 4), Ok(-61750));
+    assert_eq!(parse_temperature(b"-99.999999", 6), Ok(-99_999_999));
+    assert_eq!(parse_temperature(b"1.23456", 4), Err(RowErrorKind::UnsupportedPrecision { digits: 5, precision: 4 }));
+}
+
+#[test]
+fn test_detect_precision() {
+    assert_eq!(detect_precision(b""), DEFAULT_PRECISION);
+    assert_eq!(detect_precision(b"# comment\nHamburg;12.0\nBulawayo;8.9\n"), 1);
+    assert_eq!(detect_precision(b"Hamburg;12.0\nBulawayo;8.95\n"), 2);
+    assert_eq!(detect_precision(&std::fs::read("data/test.csv").unwrap()), 4);
+}
+
+#[test]
+fn test_validate_row() {
+    assert_eq!(validate_row(b"Hamburg;12.0", 1), Ok(("Hamburg", 120)));
+    assert_eq!(validate_row(b"St. John's;-3.4", 1), Ok(("St. John's", -34)));
+    assert_eq!(validate_row(b"", 1), Err(RowErrorKind::EmptyLine));
+    assert_eq!(validate_row(b"# comment", 1), Err(RowErrorKind::Comment));
+    assert_eq!(validate_row(b"No semicolon", 1), Err(RowErrorKind::MissingSemicolon));
+    assert_eq!(validate_row(b"Bad;name;1.0", 1), Err(RowErrorKind::InvalidTemperature));
+    assert_eq!(validate_row(b";1.0", 1), Err(RowErrorKind::EmptyName));
+    assert_eq!(validate_row(b"\xff\xfe;1.0", 1), Err(RowErrorKind::InvalidUtf8));
+    let long_name = format!("{};1.0", "a".repeat(101));
+    assert_eq!(validate_row(long_name.as_bytes(), 1), Err(RowErrorKind::NameTooLong(101)));
+}
+
+#[test]
+fn test_validate_bytes() {
+    let contents = b"Hamburg;12.0\n\nBulawayo;8.9\nNo semicolon\nPalembang;138.2\nSt. John's;15.2";
+    let report = validate_bytes(contents, None);
+    assert_eq!(report.rows, 6);
+    assert_eq!(report.error_count, 3);
+    assert_eq!(report.errors, vec![
+        RowError { line: 2, offset: 13, kind: RowErrorKind::EmptyLine },
+        RowError { line: 4, offset: 27, kind: RowErrorKind::MissingSemicolon },
+        RowError { line: 5, offset: 40, kind: RowErrorKind::TemperatureOutOfRange },
+    ]);
+    assert!(validate_bytes(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4", None).is_valid());
+
+    assert!(validate_file("data/test_small.csv", None).unwrap().is_valid());
+    let report = validate_file("data/test.csv", Some(1)).unwrap();
+    assert_eq!(report.errors[0], RowError { line: 1, offset: 0, kind: RowErrorKind::Comment });
+    assert!(validate_file("data/test.csv", None).unwrap().errors.iter().all(|error| error.kind == RowErrorKind::Comment));
+    assert_eq!(report.errors[2], RowError { line: 3, offset: 153, kind: RowErrorKind::UnsupportedPrecision { digits: 4, precision: 1 } });
+}
+
+#[test]
+fn test_rejected() {
+    let mut rejected = Rejected::default();
+    assert_eq!(rejected.to_string(), "0 rows rejected");
+    rejected.add(&RowErrorKind::Comment, b"# header", ErrorPolicy::Quarantine);
+    let mut other = Rejected::default();
+    other.add(&RowErrorKind::EmptyLine, b"", ErrorPolicy::Quarantine);
+    other.add(&RowErrorKind::Comment, b"# footer", ErrorPolicy::Skip);
+    rejected.merge(other);
+    assert_eq!(rejected.total(), 3);
+    assert_eq!(rejected.lines, b"# header\n\n");
+    assert_eq!(rejected.to_string(), "3 rows rejected (2 comment, 1 empty line)");
+
+    let quarantined = |lines: &[&[u8]]| {
+        let mut rejected = Rejected::default();
+        for line in lines {
+            rejected.add(&RowErrorKind::Comment, line, ErrorPolicy::Quarantine);
+        }
+        rejected
+    };
+    let mut rejected = quarantined(&[b"#a", b"#b"]);
+    rejected.merge_within(quarantined(&[b"#c", b"#d"]), 9);
+    assert_eq!((rejected.lines.as_slice(), rejected.dropped), (&b"#a\n#b\n#c\n"[..], 1));
+    rejected.merge_within(quarantined(&[b"#"]), 100);
+    assert_eq!((rejected.lines.len(), rejected.dropped, rejected.total()), (9, 2, 5));
+}